@@ -0,0 +1,104 @@
+// Replay an ioctl trace recorded by start_recording() outside SGX, and report the differences.
+//
+// Usage: pxp-replay TRACE [DEVICE]
+// Without DEVICE (e.g. /dev/dri/renderD128), the trace is replayed against the fake i915 driver of
+// the mock backend.
+use i915r::{replay, set_driver, Error, FakeI915, MockTransport, Transport};
+use std::ffi::c_void;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::process;
+
+const ENOSYS: i32 = 38;
+
+// The host's ioctl(), with the argument in ordinary memory.
+struct NativeTransport;
+
+impl Transport for NativeTransport {
+    fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+        let ret = unsafe { ioctl(fd, request as u64, arg) };
+        if ret < 0 {
+            return Err(Error::HostIoctl(io::Error::last_os_error().raw_os_error().unwrap_or(0)));
+        }
+        Ok(ret)
+    }
+    fn alloc(&self, size: usize, _align: usize) -> Result<*mut u8, Error> {
+        // malloc() is aligned enough for any type.
+        Ok(unsafe { malloc(size) } as *mut u8)
+    }
+    fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+        unsafe { free(ptr as *mut c_void) };
+        Ok(())
+    }
+    fn mmap(
+        &self,
+        _addr: *mut u8,
+        _len: usize,
+        _prot: i32,
+        _flags: i32,
+        _fd: i32,
+        _offset: i64,
+    ) -> Result<*mut u8, Error> {
+        Err(Error::HostMmap(ENOSYS))
+    }
+    fn munmap(&self, _addr: *mut u8, _len: usize) -> Result<(), Error> {
+        Err(Error::HostMmap(ENOSYS))
+    }
+    fn is_outside_enclave(&self, _ptr: *const u8, _size: usize) -> bool {
+        true
+    }
+}
+
+extern "C" {
+    fn ioctl(fd: i32, request: u64, ...) -> i32;
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} TRACE [DEVICE]", args[0]);
+        process::exit(2);
+    }
+    let trace = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(2);
+    });
+    let mismatches = match args.get(2) {
+        Some(path) => {
+            let device = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path, e);
+                    process::exit(2);
+                });
+            let fd = device.as_raw_fd();
+            replay(&trace, &NativeTransport, &|_| fd)
+        }
+        None => {
+            set_driver(Box::new(FakeI915::new()));
+            replay(&trace, &MockTransport, &|fd| fd)
+        }
+    };
+    match mismatches {
+        Ok(mismatches) => {
+            for mismatch in mismatches.iter() {
+                println!(
+                    "#{} cmd {}: {}",
+                    mismatch.index, mismatch.cmd, mismatch.reason
+                );
+            }
+            if !mismatches.is_empty() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("replay failed: {}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use alloc::string::String;
+use core::fmt;
+#[cfg(not(feature = "mock"))]
+use sgx_types::sgx_status_t;
+
+// The mock is built without the SGX SDK and never makes an OCALL.
+#[cfg(feature = "mock")]
+pub type sgx_status_t = u32;
+
+// errno values reported back to the caller of pxp_ioctl()
+pub const EIO: i32 = 5;
+pub const ENOMEM: i32 = 12;
+pub const EFAULT: i32 = 14;
+pub const EINVAL: i32 = 22;
+pub const ENOTTY: i32 = 25;
+
+#[derive(Debug)]
+pub enum Error {
+    // The ioctl command is not handled by this crate.
+    UnsupportedCommand(u32),
+    // The ioctl argument is malformed: illegal PXP action, unknown extension, size overflow, ...
+    InvalidArgument(String),
+    // Untrusted memory of the given size can't be allocated.
+    UntrustedAlloc(usize),
+    // The OCALL itself failed, the host was never (or only partially) reached.
+    Ocall(sgx_status_t),
+    // The host-side ioctl failed with the given errno.
+    HostIoctl(i32),
+    // The host-side mmap() or munmap() failed with the given errno.
+    HostMmap(i32),
+    // The host reported back a size, count or pointer exceeding what the enclave supplied.
+    UntrustedValue(String),
+}
+
+impl Error {
+    pub fn errno(&self) -> i32 {
+        match self {
+            Error::UnsupportedCommand(_) => ENOTTY,
+            Error::InvalidArgument(_) => EINVAL,
+            Error::UntrustedAlloc(_) => ENOMEM,
+            Error::Ocall(_) => EFAULT,
+            // Keep the host's errno (EINTR, EAGAIN, ...) so that the caller can retry. Without
+            // `propagate_errno` on the OCALL it is unknown, so report EIO instead.
+            Error::HostIoctl(errno) if *errno > 0 => *errno,
+            Error::HostIoctl(_) => EIO,
+            Error::HostMmap(errno) if *errno > 0 => *errno,
+            Error::HostMmap(_) => EIO,
+            Error::UntrustedValue(_) => EFAULT,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnsupportedCommand(cmd) => write!(f, "unsupported ioctl: {:?}", cmd),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::UntrustedAlloc(size) => {
+                write!(f, "can't allocate untrusted memory: [ 0x{:x} ]", size)
+            }
+            Error::Ocall(status) => write!(f, "ocall failed: {:?}", status),
+            Error::HostIoctl(errno) => write!(f, "host ioctl failed: errno {:?}", errno),
+            Error::HostMmap(errno) => write!(f, "host mmap failed: errno {:?}", errno),
+            Error::UntrustedValue(msg) => write!(f, "untrusted value rejected: {}", msg),
+        }
+    }
+}
+
+// Get the calling thread's errno.
+pub fn errno() -> i32 {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "mock")] {
+            unsafe { *__errno_location() }
+        } else if #[cfg(feature = "occlum")] {
+            sgx_trts::error::errno()
+        } else {
+            unsafe { *__errno() }
+        }
+    }
+}
+
+// Set the calling thread's errno, so that the caller of pxp_ioctl() can read it as usual.
+pub fn set_errno(errno: i32) {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "mock")] {
+            unsafe { *__errno_location() = errno; }
+        } else if #[cfg(feature = "occlum")] {
+            sgx_trts::error::set_errno(errno);
+        } else {
+            unsafe { *__errno() = errno; }
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "mock")] {
+        extern "C" {
+            // Provided by the host's libc
+            fn __errno_location() -> *mut i32;
+        }
+    } else if #[cfg(not(feature = "occlum"))] {
+        extern "C" {
+            // Provided by SGX SDK's tlibc
+            fn __errno() -> *mut i32;
+        }
+    }
+}
@@ -0,0 +1,807 @@
+use crate::i915::{
+    DRM_IOCTL_GEM_CLOSE, DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT, DRM_IOCTL_I915_GEM_CONTEXT_DESTROY,
+    DRM_IOCTL_I915_GEM_CREATE, DRM_IOCTL_I915_GEM_CREATE_EXT, DRM_IOCTL_I915_GEM_EXECBUFFER2,
+    DRM_IOCTL_I915_GEM_EXECBUFFER2_WR, DRM_IOCTL_I915_GEM_PREAD, DRM_IOCTL_I915_GEM_PWRITE,
+    DRM_IOCTL_I915_GEM_VM_CREATE, DRM_IOCTL_I915_GEM_VM_DESTROY,
+    DRM_IOCTL_I915_GETPARAM, DRM_IOCTL_I915_QUERY, DRM_IOCTL_VERSION, I915_EXEC_FENCE_ARRAY,
+    I915_EXEC_USE_EXTENSIONS, PRELIM_DRM_IOCTL_I915_PXP_OPS, PRELIM_I915_USER_EXT_MASK,
+};
+use crate::error::{EFAULT, EINVAL, ENOTTY};
+use crate::mock::{in_arena, FakeDriver};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use spin::{Mutex, MutexGuard};
+
+// A software model of the i915 driver, for the ioctls dispatched by pxp_ioctl(). It keeps enough
+// state to replay whole call sequences: GEM objects, contexts, VMs, PXP sessions and the submitted
+// execbuffers. Every pointer it is given must be in the mock arena, the others are recorded in
+// `violations` and fail with EFAULT.
+//
+// The structures below are the kernel's view of the uapi. They are declared separately from i915.rs
+// on purpose, so that a layout mistake there doesn't cancel out.
+
+const ENOENT: i32 = 2;
+const E2BIG: i32 = 7;
+const PAGE_SIZE: u64 = 4096;
+// The kernel gives up walking an extension chain after this many nodes.
+const MAX_EXTENSIONS: usize = 512;
+const PXP_MAX_SESSIONS: u32 = 16;
+
+// PRELIM_DRM_I915_PXP_ACTION_*
+const PXP_ACTION_SET_SESSION_STATUS: u32 = 0;
+const PXP_ACTION_TEE_IO_MESSAGE: u32 = 1;
+const PXP_ACTION_QUERY_PXP_TAG: u32 = 2;
+// PRELIM_DRM_I915_PXP_REQ_SESSION_*
+const PXP_REQ_SESSION_ID_INIT: u32 = 0;
+const PXP_REQ_SESSION_IN_PLAY: u32 = 1;
+const PXP_REQ_SESSION_TERMINATE: u32 = 2;
+// PRELIM_DRM_I915_PXP_OP_STATUS_*
+const PXP_OP_STATUS_SUCCESS: u32 = 0;
+const PXP_OP_STATUS_SESSION_NOT_AVAILABLE: u32 = 2;
+
+const I915_CONTEXT_CREATE_FLAGS_USE_EXTENSIONS: u32 = 1 << 0;
+const I915_CONTEXT_CREATE_EXT_SETPARAM: u32 = 0;
+const PRELIM_I915_OBJECT_PARAM_MEMORY_REGIONS: u32 = 1;
+const PRELIM_I915_GEM_CREATE_EXT_PROTECTED_CONTENT: u32 = 3;
+const I915_EXEC_FENCE_OUT: u64 = 1 << 17;
+const I915_EXEC_BATCH_FIRST: u64 = 1 << 18;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct UserExtension {
+    next_extension: u64,
+    name: u32,
+    flags: u32,
+    rsvd: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Version {
+    version_major: i32,
+    version_minor: i32,
+    version_patchlevel: i32,
+    name_len: u64,
+    name: u64,
+    date_len: u64,
+    date: u64,
+    desc_len: u64,
+    desc: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GetParam {
+    param: i32,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GemCreate {
+    size: u64,
+    handle: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GemCreateExt {
+    size: u64,
+    handle: u32,
+    pad: u32,
+    extensions: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct CreateExtSetParam {
+    base: UserExtension,
+    handle: u32,
+    size: u32,
+    param: u64,
+    data: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GemClose {
+    handle: u32,
+    pad: u32,
+}
+
+// Both drm_i915_gem_pread and drm_i915_gem_pwrite
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GemRw {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+    size: u64,
+    data_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Query {
+    num_items: u32,
+    flags: u32,
+    items_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct QueryItem {
+    query_id: u64,
+    length: i32,
+    flags: u32,
+    data_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ContextCreateExt {
+    ctx_id: u32,
+    flags: u32,
+    extensions: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ContextSetParam {
+    base: UserExtension,
+    ctx_id: u32,
+    size: u32,
+    param: u64,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VmControl {
+    extensions: u64,
+    flags: u32,
+    vm_id: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Execbuffer2 {
+    buffers_ptr: u64,
+    buffer_count: u32,
+    batch_start_offset: u32,
+    batch_len: u32,
+    dr1: u32,
+    dr4: u32,
+    num_cliprects: u32,
+    cliprects_ptr: u64,
+    flags: u64,
+    rsvd1: u64,
+    rsvd2: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ExecObject2 {
+    handle: u32,
+    relocation_count: u32,
+    relocs_ptr: u64,
+    alignment: u64,
+    offset: u64,
+    flags: u64,
+    rsvd1: u64,
+    rsvd2: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ExecFence {
+    handle: u32,
+    flags: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct PxpOps {
+    action: u32,
+    status: u32,
+    params: u64,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct PxpSetSessionStatus {
+    pxp_tag: u32,
+    session_type: u32,
+    session_mode: u32,
+    req_session_state: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct PxpTeeIoMessage {
+    msg_in: u64,
+    msg_in_size: u32,
+    msg_out: u64,
+    msg_out_buf_size: u32,
+    msg_out_ret_size: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct PxpQueryTag {
+    session_is_alive: u32,
+    pxp_tag: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeObject {
+    pub size: u64,
+    // The memory regions of PRELIM_I915_OBJECT_PARAM_MEMORY_REGIONS, as (class, instance).
+    pub placements: Vec<(u16, u16)>,
+    pub protected: bool,
+    // The bytes written by DRM_IOCTL_I915_GEM_PWRITE, the rest of the object reads as zeroes.
+    pub contents: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SessionState {
+    // Reserved by PRELIM_DRM_I915_PXP_REQ_SESSION_ID_INIT
+    Reserved,
+    // Marked by PRELIM_DRM_I915_PXP_REQ_SESSION_IN_PLAY
+    InPlay,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeSession {
+    pub session_type: u32,
+    pub session_mode: u32,
+    pub state: SessionState,
+}
+
+// A submitted execbuffer, in order of submission.
+#[derive(Debug, Clone)]
+pub struct FakeExecbuffer {
+    pub fd: i32,
+    pub ctx_id: u32,
+    pub handles: Vec<u32>,
+    pub batch_handle: u32,
+    pub batch_start_offset: u32,
+    pub batch_len: u32,
+    pub flags: u64,
+}
+
+pub struct I915State {
+    pub version: (i32, i32, i32),
+    pub name: String,
+    pub date: String,
+    pub desc: String,
+    // The values of DRM_IOCTL_I915_GETPARAM, the others fail with EINVAL.
+    pub params: BTreeMap<i32, i32>,
+    // The payloads of DRM_IOCTL_I915_QUERY by query_id, the others fail with EINVAL.
+    pub query_items: BTreeMap<u64, Vec<u8>>,
+    // [ (fd, handle) -> object ]
+    pub objects: BTreeMap<(i32, u32), FakeObject>,
+    // (fd, ctx_id)
+    pub contexts: BTreeSet<(i32, u32)>,
+    // (fd, vm_id)
+    pub vms: BTreeSet<(i32, u32)>,
+    // [ pxp_tag -> session ]
+    pub sessions: BTreeMap<u32, FakeSession>,
+    pub execbuffers: Vec<FakeExecbuffer>,
+    // The pointers which aren't in the mock arena, i.e. which would point inside enclave.
+    pub violations: Vec<String>,
+    next_handle: u32,
+    next_id: u32,
+    next_fence_fd: i32,
+}
+
+impl I915State {
+    fn new() -> I915State {
+        I915State {
+            version: (1, 6, 0),
+            name: String::from("i915"),
+            date: String::from("20201103"),
+            desc: String::from("Intel Graphics"),
+            params: BTreeMap::new(),
+            query_items: BTreeMap::new(),
+            objects: BTreeMap::new(),
+            contexts: BTreeSet::new(),
+            vms: BTreeSet::new(),
+            sessions: BTreeMap::new(),
+            execbuffers: Vec::new(),
+            violations: Vec::new(),
+            next_handle: 1,
+            next_id: 1,
+            next_fence_fd: 1000,
+        }
+    }
+
+    // Check that [ ptr, ptr + size ) can be accessed by the kernel.
+    fn check(&mut self, what: &str, ptr: u64, size: usize) -> Result<(), i32> {
+        if size == 0 {
+            return Ok(());
+        }
+        if ptr == 0 {
+            return Err(EFAULT);
+        }
+        if !in_arena(ptr as *const u8, size) {
+            self.violations.push(format!(
+                "{}: [ 0x{:x}, 0x{:x} ] isn't untrusted memory",
+                what, ptr, size
+            ));
+            return Err(EFAULT);
+        }
+        Ok(())
+    }
+
+    fn read<T: Copy>(&mut self, what: &str, ptr: u64) -> Result<T, i32> {
+        self.check(what, ptr, mem::size_of::<T>())?;
+        Ok(unsafe { ptr::read_unaligned(ptr as *const T) })
+    }
+
+    fn write<T: Copy>(&mut self, what: &str, ptr: u64, value: T) -> Result<(), i32> {
+        self.check(what, ptr, mem::size_of::<T>())?;
+        unsafe { ptr::write_unaligned(ptr as *mut T, value) };
+        Ok(())
+    }
+
+    fn read_bytes(&mut self, what: &str, ptr: u64, size: usize) -> Result<Vec<u8>, i32> {
+        self.check(what, ptr, size)?;
+        let mut bytes = vec![0u8; size];
+        if size > 0 {
+            unsafe { ptr::copy(ptr as *const u8, bytes.as_mut_ptr(), size) };
+        }
+        Ok(bytes)
+    }
+
+    fn write_bytes(&mut self, what: &str, ptr: u64, bytes: &[u8]) -> Result<(), i32> {
+        self.check(what, ptr, bytes.len())?;
+        if !bytes.is_empty() {
+            unsafe { ptr::copy(bytes.as_ptr(), ptr as *mut u8, bytes.len()) };
+        }
+        Ok(())
+    }
+
+    // Walk a chain of i915_user_extension, calling f with the address and the name of each node.
+    fn extensions<F>(&mut self, what: &str, mut next: u64, mut f: F) -> Result<(), i32>
+    where
+        F: FnMut(&mut I915State, u64, u32) -> Result<(), i32>,
+    {
+        let mut count = 0;
+        while next != 0 {
+            count += 1;
+            if count > MAX_EXTENSIONS {
+                return Err(E2BIG);
+            }
+            let ext = self.read::<UserExtension>(what, next)?;
+            f(self, next, ext.name)?;
+            next = ext.next_extension;
+        }
+        Ok(())
+    }
+
+    fn new_handle(&mut self) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn ioctl(&mut self, fd: i32, request: u32, arg: u64) -> Result<i32, i32> {
+        match request {
+            DRM_IOCTL_VERSION => self.version_ioctl(arg),
+            DRM_IOCTL_I915_GETPARAM => self.getparam_ioctl(arg),
+            DRM_IOCTL_I915_GEM_CREATE => self.gem_create_ioctl(fd, arg, false),
+            DRM_IOCTL_I915_GEM_CREATE_EXT => self.gem_create_ioctl(fd, arg, true),
+            DRM_IOCTL_GEM_CLOSE => self.gem_close_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_PREAD => self.gem_pread_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_PWRITE => self.gem_pwrite_ioctl(fd, arg),
+            DRM_IOCTL_I915_QUERY => self.query_ioctl(arg),
+            DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT => self.context_create_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_CONTEXT_DESTROY => self.context_destroy_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_VM_CREATE => self.vm_create_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_VM_DESTROY => self.vm_destroy_ioctl(fd, arg),
+            DRM_IOCTL_I915_GEM_EXECBUFFER2 => self.execbuffer2_ioctl(fd, arg, false),
+            DRM_IOCTL_I915_GEM_EXECBUFFER2_WR => self.execbuffer2_ioctl(fd, arg, true),
+            PRELIM_DRM_IOCTL_I915_PXP_OPS => self.pxp_ops_ioctl(arg),
+            _ => Err(ENOTTY),
+        }
+    }
+
+    // Like drm_copy_field(): copy as much of the string as fits, and report its full length.
+    fn copy_field(&mut self, what: &str, len: &mut u64, buf: u64, value: &[u8]) -> Result<(), i32> {
+        let size = (*len as usize).min(value.len());
+        if size > 0 {
+            self.write_bytes(what, buf, &value[..size])?;
+        }
+        *len = value.len() as u64;
+        Ok(())
+    }
+
+    fn version_ioctl(&mut self, arg: u64) -> Result<i32, i32> {
+        let mut version = self.read::<Version>("version", arg)?;
+        version.version_major = self.version.0;
+        version.version_minor = self.version.1;
+        version.version_patchlevel = self.version.2;
+        let (name, date, desc) = (self.name.clone(), self.date.clone(), self.desc.clone());
+        self.copy_field("name", &mut version.name_len, version.name, name.as_bytes())?;
+        self.copy_field("date", &mut version.date_len, version.date, date.as_bytes())?;
+        self.copy_field("desc", &mut version.desc_len, version.desc, desc.as_bytes())?;
+        self.write("version", arg, version)?;
+        Ok(0)
+    }
+
+    fn getparam_ioctl(&mut self, arg: u64) -> Result<i32, i32> {
+        let getparam = self.read::<GetParam>("getparam", arg)?;
+        let value = *self.params.get(&getparam.param).ok_or(EINVAL)?;
+        self.write("getparam value", getparam.value, value)?;
+        Ok(0)
+    }
+
+    fn gem_create_ioctl(&mut self, fd: i32, arg: u64, ext: bool) -> Result<i32, i32> {
+        let mut create = if ext {
+            self.read::<GemCreateExt>("gem_create_ext", arg)?
+        } else {
+            let create = self.read::<GemCreate>("gem_create", arg)?;
+            GemCreateExt {
+                size: create.size,
+                handle: 0,
+                pad: 0,
+                extensions: 0,
+            }
+        };
+        let size = create.size.checked_add(PAGE_SIZE - 1).ok_or(EINVAL)? & !(PAGE_SIZE - 1);
+        if size == 0 {
+            return Err(EINVAL);
+        }
+        let mut object = FakeObject {
+            size,
+            placements: Vec::new(),
+            protected: false,
+            contents: Vec::new(),
+        };
+        self.extensions(
+            "gem_create_ext extension",
+            create.extensions,
+            |state, addr, name| {
+                match name & PRELIM_I915_USER_EXT_MASK {
+                    1 => {
+                        let setparam = state.read::<CreateExtSetParam>("setparam", addr)?;
+                        if setparam.param as u32 != PRELIM_I915_OBJECT_PARAM_MEMORY_REGIONS {
+                            return Err(EINVAL);
+                        }
+                        let data = state.read_bytes(
+                            "setparam data",
+                            setparam.data,
+                            setparam.size as usize * 4,
+                        )?;
+                        object.placements = data
+                            .chunks(4)
+                            .map(|r| {
+                                (
+                                    u16::from_ne_bytes([r[0], r[1]]),
+                                    u16::from_ne_bytes([r[2], r[3]]),
+                                )
+                            })
+                            .collect();
+                        Ok(())
+                    }
+                    // The VM of a VM-private object isn't tracked.
+                    2 => Ok(()),
+                    PRELIM_I915_GEM_CREATE_EXT_PROTECTED_CONTENT => {
+                        object.protected = true;
+                        Ok(())
+                    }
+                    _ => Err(EINVAL),
+                }
+            },
+        )?;
+        let handle = self.new_handle();
+        self.objects.insert((fd, handle), object);
+        create.size = size;
+        create.handle = handle;
+        if ext {
+            self.write("gem_create_ext", arg, create)?;
+        } else {
+            self.write(
+                "gem_create",
+                arg,
+                GemCreate {
+                    size,
+                    handle,
+                    pad: 0,
+                },
+            )?;
+        }
+        Ok(0)
+    }
+
+    fn gem_close_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let close = self.read::<GemClose>("gem_close", arg)?;
+        self.objects.remove(&(fd, close.handle)).ok_or(EINVAL)?;
+        Ok(0)
+    }
+
+    // The range [ offset, offset + size ) of an object, like i915_gem_pread_ioctl() checks it.
+    fn object_range(&mut self, fd: i32, rw: &GemRw) -> Result<&mut FakeObject, i32> {
+        let object = self.objects.get_mut(&(fd, rw.handle)).ok_or(ENOENT)?;
+        match rw.offset.checked_add(rw.size) {
+            Some(end) if end <= object.size => Ok(object),
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn gem_pread_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let pread = self.read::<GemRw>("gem_pread", arg)?;
+        let object = self.object_range(fd, &pread)?;
+        let mut bytes = vec![0u8; pread.size as usize];
+        let start = (pread.offset as usize).min(object.contents.len());
+        let end = ((pread.offset + pread.size) as usize).min(object.contents.len());
+        bytes[..end - start].copy_from_slice(&object.contents[start..end]);
+        self.write_bytes("gem_pread data", pread.data_ptr, &bytes)?;
+        Ok(0)
+    }
+
+    fn gem_pwrite_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let pwrite = self.read::<GemRw>("gem_pwrite", arg)?;
+        self.object_range(fd, &pwrite)?;
+        let bytes = self.read_bytes("gem_pwrite data", pwrite.data_ptr, pwrite.size as usize)?;
+        let object = self.object_range(fd, &pwrite)?;
+        let (start, end) = (pwrite.offset as usize, (pwrite.offset + pwrite.size) as usize);
+        if object.contents.len() < end {
+            object.contents.resize(end, 0);
+        }
+        object.contents[start..end].copy_from_slice(&bytes);
+        Ok(0)
+    }
+
+    // The two-pass protocol: a length of 0 queries the length needed, a too short one fails.
+    // Errors of an item are reported in its length, not by the ioctl.
+    fn query_ioctl(&mut self, arg: u64) -> Result<i32, i32> {
+        let query = self.read::<Query>("query", arg)?;
+        if query.flags != 0 {
+            return Err(EINVAL);
+        }
+        let size = mem::size_of::<QueryItem>();
+        self.check(
+            "query items",
+            query.items_ptr,
+            size * query.num_items as usize,
+        )?;
+        for i in 0..query.num_items as u64 {
+            let addr = query.items_ptr + i * size as u64;
+            let mut item = self.read::<QueryItem>("query item", addr)?;
+            item.length = match self.query_items.get(&item.query_id).cloned() {
+                None => -EINVAL,
+                Some(data) if item.length == 0 => data.len() as i32,
+                Some(data) if (item.length as usize) < data.len() || item.length < 0 => -EINVAL,
+                Some(data) => match self.write_bytes("query item data", item.data_ptr, &data) {
+                    Ok(()) => data.len() as i32,
+                    Err(errno) => -errno,
+                },
+            };
+            self.write("query item", addr, item)?;
+        }
+        Ok(0)
+    }
+
+    fn context_create_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let mut create = self.read::<ContextCreateExt>("context_create_ext", arg)?;
+        if create.flags & I915_CONTEXT_CREATE_FLAGS_USE_EXTENSIONS != 0 {
+            self.extensions(
+                "context_create_ext extension",
+                create.extensions,
+                |state, addr, name| {
+                    if name == I915_CONTEXT_CREATE_EXT_SETPARAM {
+                        let setparam = state.read::<ContextSetParam>("setparam", addr)?;
+                        state.check("setparam value", setparam.value, setparam.size as usize)?;
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        create.ctx_id = self.new_id();
+        self.contexts.insert((fd, create.ctx_id));
+        self.write("context_create_ext", arg, create)?;
+        Ok(0)
+    }
+
+    fn context_destroy_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let destroy = self.read::<GemClose>("context_destroy", arg)?;
+        if !self.contexts.remove(&(fd, destroy.handle)) {
+            return Err(ENOENT);
+        }
+        Ok(0)
+    }
+
+    fn vm_create_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let mut control = self.read::<VmControl>("vm_control", arg)?;
+        self.extensions("vm_control extension", control.extensions, |_, _, _| Ok(()))?;
+        control.vm_id = self.new_id();
+        self.vms.insert((fd, control.vm_id));
+        self.write("vm_control", arg, control)?;
+        Ok(0)
+    }
+
+    fn vm_destroy_ioctl(&mut self, fd: i32, arg: u64) -> Result<i32, i32> {
+        let control = self.read::<VmControl>("vm_control", arg)?;
+        if !self.vms.remove(&(fd, control.vm_id)) {
+            return Err(ENOENT);
+        }
+        Ok(0)
+    }
+
+    fn execbuffer2_ioctl(&mut self, fd: i32, arg: u64, wr: bool) -> Result<i32, i32> {
+        let mut execbuffer = self.read::<Execbuffer2>("execbuffer2", arg)?;
+        if execbuffer.buffer_count == 0 {
+            return Err(EINVAL);
+        }
+        let ctx_id = execbuffer.rsvd1 as u32;
+        if ctx_id != 0 && !self.contexts.contains(&(fd, ctx_id)) {
+            return Err(ENOENT);
+        }
+        if execbuffer.flags & I915_EXEC_USE_EXTENSIONS != 0 {
+            self.extensions(
+                "execbuffer2 extension",
+                execbuffer.cliprects_ptr,
+                |_, _, _| Ok(()),
+            )?;
+        } else if execbuffer.flags & I915_EXEC_FENCE_ARRAY != 0 {
+            self.check(
+                "execbuffer2 fences",
+                execbuffer.cliprects_ptr,
+                mem::size_of::<ExecFence>() * execbuffer.num_cliprects as usize,
+            )?;
+        }
+        let size = mem::size_of::<ExecObject2>();
+        self.check(
+            "exec objects",
+            execbuffer.buffers_ptr,
+            size * execbuffer.buffer_count as usize,
+        )?;
+        let mut handles = Vec::new();
+        for i in 0..execbuffer.buffer_count as u64 {
+            let addr = execbuffer.buffers_ptr + i * size as u64;
+            let mut object = self.read::<ExecObject2>("exec object", addr)?;
+            if !self.objects.contains_key(&(fd, object.handle)) {
+                return Err(ENOENT);
+            }
+            // A fake GPU address, stable for a given object.
+            object.offset = (object.handle as u64) << 32;
+            self.write("exec object", addr, object)?;
+            handles.push(object.handle);
+        }
+        let batch_handle = if execbuffer.flags & I915_EXEC_BATCH_FIRST != 0 {
+            handles[0]
+        } else {
+            handles[handles.len() - 1]
+        };
+        self.execbuffers.push(FakeExecbuffer {
+            fd,
+            ctx_id,
+            handles,
+            batch_handle,
+            batch_start_offset: execbuffer.batch_start_offset,
+            batch_len: execbuffer.batch_len,
+            flags: execbuffer.flags,
+        });
+        // The out fence fd is returned in the upper 32 bits of rsvd2.
+        if wr && execbuffer.flags & I915_EXEC_FENCE_OUT != 0 {
+            let fence_fd = self.next_fence_fd;
+            self.next_fence_fd += 1;
+            execbuffer.rsvd2 = (execbuffer.rsvd2 & 0xffff_ffff) | ((fence_fd as u64) << 32);
+            self.write("execbuffer2", arg, execbuffer)?;
+        }
+        Ok(0)
+    }
+
+    fn pxp_ops_ioctl(&mut self, arg: u64) -> Result<i32, i32> {
+        let mut ops = self.read::<PxpOps>("pxp_ops", arg)?;
+        let params = ops.params;
+        ops.status = match ops.action {
+            PXP_ACTION_SET_SESSION_STATUS => {
+                let mut status = self.read::<PxpSetSessionStatus>("pxp session status", params)?;
+                let result = self.set_session_status(&mut status)?;
+                self.write("pxp session status", params, status)?;
+                result
+            }
+            PXP_ACTION_TEE_IO_MESSAGE => {
+                // The firmware is an echo: the reply is the message.
+                let mut io = self.read::<PxpTeeIoMessage>("pxp tee io", params)?;
+                let msg = self.read_bytes("pxp tee msg_in", io.msg_in, io.msg_in_size as usize)?;
+                let size = msg.len().min(io.msg_out_buf_size as usize);
+                self.check("pxp tee msg_out", io.msg_out, io.msg_out_buf_size as usize)?;
+                self.write_bytes("pxp tee msg_out", io.msg_out, &msg[..size])?;
+                io.msg_out_ret_size = size as u32;
+                self.write("pxp tee io", params, io)?;
+                PXP_OP_STATUS_SUCCESS
+            }
+            PXP_ACTION_QUERY_PXP_TAG => {
+                let mut query = self.read::<PxpQueryTag>("pxp query tag", params)?;
+                let tag = query.pxp_tag;
+                query.session_is_alive = match self.sessions.get(&tag) {
+                    Some(session) if session.state == SessionState::InPlay => 1,
+                    _ => 0,
+                };
+                self.write("pxp query tag", params, query)?;
+                PXP_OP_STATUS_SUCCESS
+            }
+            _ => return Err(EINVAL),
+        };
+        self.write("pxp_ops", arg, ops)?;
+        Ok(0)
+    }
+
+    // The session state machine: ID_INIT reserves a session and returns its tag, IN_PLAY marks a
+    // reserved session as in play, TERMINATE releases it.
+    fn set_session_status(&mut self, status: &mut PxpSetSessionStatus) -> Result<u32, i32> {
+        let tag = status.pxp_tag;
+        match status.req_session_state {
+            PXP_REQ_SESSION_ID_INIT => {
+                let tag = match (0..PXP_MAX_SESSIONS).find(|tag| !self.sessions.contains_key(tag)) {
+                    Some(tag) => tag,
+                    None => return Ok(PXP_OP_STATUS_SESSION_NOT_AVAILABLE),
+                };
+                self.sessions.insert(
+                    tag,
+                    FakeSession {
+                        session_type: status.session_type,
+                        session_mode: status.session_mode,
+                        state: SessionState::Reserved,
+                    },
+                );
+                status.pxp_tag = tag;
+                Ok(PXP_OP_STATUS_SUCCESS)
+            }
+            PXP_REQ_SESSION_IN_PLAY => {
+                self.sessions.get_mut(&tag).ok_or(EINVAL)?.state = SessionState::InPlay;
+                Ok(PXP_OP_STATUS_SUCCESS)
+            }
+            PXP_REQ_SESSION_TERMINATE => {
+                self.sessions.remove(&tag).ok_or(EINVAL)?;
+                Ok(PXP_OP_STATUS_SUCCESS)
+            }
+            _ => Err(EINVAL),
+        }
+    }
+}
+
+// The model, to be installed with set_driver(). Clones share the state, so a test can keep one to
+// configure and inspect the driver.
+#[derive(Clone)]
+pub struct FakeI915 {
+    state: Arc<Mutex<I915State>>,
+}
+
+impl FakeI915 {
+    pub fn new() -> FakeI915 {
+        FakeI915 {
+            state: Arc::new(Mutex::new(I915State::new())),
+        }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, I915State> {
+        self.state.lock()
+    }
+}
+
+impl Default for FakeI915 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeDriver for FakeI915 {
+    fn ioctl(&mut self, fd: i32, request: u32, arg: *mut u8) -> Result<i32, i32> {
+        self.state.lock().ioctl(fd, request, arg as u64)
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+// How a GEM object got its handle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Origin {
+    // DRM_IOCTL_I915_GEM_CREATE
+    Create,
+    // DRM_IOCTL_I915_GEM_CREATE_EXT
+    CreateExt,
+    // DRM_IOCTL_I915_GEM_USERPTR, with the untrusted user_ptr
+    Userptr(u64),
+    // DRM_IOCTL_PRIME_FD_TO_HANDLE, with the dma-buf fd
+    Prime(i32),
+    // DRM_IOCTL_GEM_OPEN, with the flink name
+    Flink(u32),
+}
+
+// A memory region the object may be placed in: (memory_class, memory_instance).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MemoryRegion {
+    pub memory_class: u16,
+    pub memory_instance: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct GemObject {
+    pub handle: u32,
+    // The size in bytes, unknown for an imported dma-buf.
+    pub size: Option<u64>,
+    // Empty if the kernel chooses the placement.
+    pub placements: Vec<MemoryRegion>,
+    pub protected: bool,
+    pub origin: Origin,
+}
+
+// The live GEM objects: [ (fd, handle) -> object ]. A handle is only meaningful for the fd it was
+// returned on.
+static OBJECTS: Mutex<BTreeMap<(i32, u32), GemObject>> = Mutex::new(BTreeMap::new());
+
+pub fn insert_object(fd: i32, object: GemObject) {
+    // A handle returned by the kernel is live, so a record left for it is stale, e.g. from an fd
+    // closed without pxp_forget_fd() whose number was reused.
+    OBJECTS.lock().insert((fd, object.handle), object);
+}
+
+pub fn lookup_object(fd: i32, handle: u32) -> Option<GemObject> {
+    OBJECTS.lock().get(&(fd, handle)).cloned()
+}
+
+pub fn remove_object(fd: i32, handle: u32) -> Option<GemObject> {
+    OBJECTS.lock().remove(&(fd, handle))
+}
+
+// Enumerate the live GEM objects of fd, e.g. to report the leaked ones before closing it.
+pub fn live_objects(fd: i32) -> Vec<GemObject> {
+    OBJECTS
+        .lock()
+        .range((fd, 0)..=(fd, u32::MAX))
+        .map(|(_, object)| object.clone())
+        .collect()
+}
+
+// Returns the objects of fd which weren't closed, they are released by the kernel with the fd.
+pub fn forget_fd(fd: i32) -> Vec<GemObject> {
+    let mut objects = OBJECTS.lock();
+    let handles: Vec<u32> = objects
+        .range((fd, 0)..=(fd, u32::MAX))
+        .map(|(&(_, handle), _)| handle)
+        .collect();
+    handles
+        .into_iter()
+        .filter_map(|handle| objects.remove(&(fd, handle)))
+        .collect()
+}
+
+// To be called once the DRM fd is closed, before its number can be reused: the handles and fake
+// offsets of the fd are only meaningful as long as it's open.
+#[no_mangle]
+pub fn pxp_forget_fd(fd: i32) {
+    let leaked = forget_fd(fd);
+    if !leaked.is_empty() {
+        debug!("PXP fd {}: {} GEM objects weren't closed", fd, leaked.len());
+    }
+    crate::mmap::forget_fd(fd);
+}
@@ -1,35 +1,59 @@
-use crate::memory::{alloc, free};
+use crate::error::{set_errno, Error};
+use crate::gem::{insert_object, lookup_object, remove_object, GemObject, MemoryRegion, Origin};
+use crate::memory::{alloc, allocation_of, free};
+use crate::mmap::{close_handle, insert_mapping, insert_offset, MmapType};
+use crate::strace::{traced, IoctlFamily, TARGET};
+use crate::trace::{self, Pointer};
+use crate::transport::transport;
 use alloc::borrow::ToOwned;
 use alloc::string::String;
-use core::{mem, ptr};
-use sgx_types::sgx_status_t;
+use alloc::vec::Vec;
+use core::{fmt, mem, ptr};
 
-const PRELIM_I915_USER_EXT_MASK: u32 = 0xffff;
+const PRELIM_I915_USER_EXT: u32 = 1 << 16;
+pub(crate) const PRELIM_I915_USER_EXT_MASK: u32 = 0xffff;
+#[allow(clippy::identity_op)]
+const PRELIM_I915_GEM_VM_CONTROL_EXT_REGION: u32 = PRELIM_I915_USER_EXT | 0;
+const I915_CONTEXT_PARAM_SSEU: u64 = 0x7;
 const I915_CONTEXT_PARAM_ENGINES: u64 = 0xa;
+const DRM_I915_PERF_PROP_GLOBAL_SSEU: u64 = 7;
+pub(crate) const I915_EXEC_FENCE_ARRAY: u64 = 1 << 19;
+pub(crate) const I915_EXEC_USE_EXTENSIONS: u64 = 1 << 21;
 
-const DRM_IOCTL_GEM_CLOSE: u32 = 1074291721;
-const PRELIM_DRM_IOCTL_I915_PXP_OPS: u32 = 3222299794;
-const DRM_IOCTL_I915_GEM_CREATE_EXT: u32 = 3222824027;
-const DRM_IOCTL_I915_QUERY: u32 = 3222299769;
+pub(crate) const DRM_IOCTL_GEM_CLOSE: u32 = 1074291721;
+pub(crate) const PRELIM_DRM_IOCTL_I915_PXP_OPS: u32 = 3222299794;
+pub(crate) const DRM_IOCTL_I915_GEM_CREATE_EXT: u32 = 3222824027;
+pub(crate) const DRM_IOCTL_I915_QUERY: u32 = 3222299769;
 const DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM: u32 = 3222824052;
 const DRM_IOCTL_I915_GEM_CONTEXT_SETPARAM: u32 = 3222824053;
 const DRM_IOCTL_GET_MAGIC: u32 = 2147771394;
 const DRM_IOCTL_AUTH_MAGIC: u32 = 1074029585;
-const DRM_IOCTL_VERSION: u32 = 3225445376;
-const DRM_IOCTL_I915_GETPARAM: u32 = 3222299718;
-const DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT: u32 = 3222299757;
-const DRM_IOCTL_I915_GEM_VM_CREATE: u32 = 3222299770;
-const DRM_IOCTL_I915_GEM_VM_DESTROY: u32 = 1074816123;
+pub(crate) const DRM_IOCTL_VERSION: u32 = 3225445376;
+const DRM_IOCTL_GET_UNIQUE: u32 = 3222299649;
+const DRM_IOCTL_GET_CAP: u32 = 3222299660;
+const DRM_IOCTL_SET_CLIENT_CAP: u32 = 1074816013;
+const DRM_IOCTL_SET_VERSION: u32 = 3222299655;
+const DRM_IOCTL_GEM_FLINK: u32 = 3221775370;
+const DRM_IOCTL_GEM_OPEN: u32 = 3222299659;
+pub(crate) const DRM_IOCTL_I915_GETPARAM: u32 = 3222299718;
+pub(crate) const DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT: u32 = 3222299757;
+pub(crate) const DRM_IOCTL_I915_GEM_VM_CREATE: u32 = 3222299770;
+pub(crate) const DRM_IOCTL_I915_GEM_VM_DESTROY: u32 = 1074816123;
 const DRM_IOCTL_I915_GEM_MMAP_OFFSET: u32 = 3223348324;
 const DRM_IOCTL_I915_GET_RESET_STATS: u32 = 3222824050;
 const DRM_IOCTL_I915_GEM_GET_APERTURE: u32 = 2148557923;
 const DRM_IOCTL_I915_GEM_SET_DOMAIN: u32 = 1074553951;
-const DRM_IOCTL_I915_GEM_EXECBUFFER2_WR: u32 = 3225445481;
-const DRM_IOCTL_I915_GEM_EXECBUFFER2: u32 = 1077961833;
+pub(crate) const DRM_IOCTL_I915_GEM_EXECBUFFER2_WR: u32 = 3225445481;
+pub(crate) const DRM_IOCTL_I915_GEM_EXECBUFFER2: u32 = 1077961833;
 const DRM_IOCTL_I915_GEM_USERPTR: u32 = 3222824051;
 const DRM_IOCTL_I915_GEM_GET_TILING: u32 = 3222299746;
+const DRM_IOCTL_I915_GEM_SET_TILING: u32 = 3222299745;
+const DRM_IOCTL_I915_GEM_SET_CACHING: u32 = 1074291823;
+const DRM_IOCTL_I915_GEM_GET_CACHING: u32 = 3221775472;
+const DRM_IOCTL_I915_GEM_THROTTLE: u32 = 25688;
+pub(crate) const DRM_IOCTL_I915_GEM_CREATE: u32 = 3222299739;
 const DRM_IOCTL_I915_GEM_WAIT: u32 = 3222299756;
-const DRM_IOCTL_I915_GEM_CONTEXT_DESTROY: u32 = 1074291822;
+pub(crate) const DRM_IOCTL_I915_GEM_CONTEXT_DESTROY: u32 = 1074291822;
 const DRM_IOCTL_I915_REG_READ: u32 = 3222299761;
 const DRM_IOCTL_I915_GEM_BUSY: u32 = 3221775447;
 const DRM_IOCTL_PRIME_HANDLE_TO_FD: u32 = 3222037549;
@@ -37,9 +61,31 @@ const DRM_IOCTL_PRIME_FD_TO_HANDLE: u32 = 3222037550;
 const DRM_IOCTL_I915_GET_PIPE_FROM_CRTC_ID: u32 = 3221775461;
 const DRM_IOCTL_I915_GEM_SW_FINISH: u32 = 1074029664;
 const DRM_IOCTL_I915_GEM_MADVISE: u32 = 3222037606;
-const DRM_IOCTL_I915_GEM_PREAD: u32 = 1075864668;
-const DRM_IOCTL_I915_GEM_PWRITE: u32 = 1075864669;
+pub(crate) const DRM_IOCTL_I915_GEM_PREAD: u32 = 1075864668;
+pub(crate) const DRM_IOCTL_I915_GEM_PWRITE: u32 = 1075864669;
 const DRM_IOCTL_I915_GEM_MMAP: u32 = 3223872606;
+const DRM_IOCTL_I915_PERF_OPEN: u32 = 1074816118;
+const DRM_IOCTL_I915_PERF_ADD_CONFIG: u32 = 1078486135;
+const DRM_IOCTL_I915_PERF_REMOVE_CONFIG: u32 = 1074291832;
+const I915_PERF_IOCTL_ENABLE: u32 = 26880;
+const I915_PERF_IOCTL_DISABLE: u32 = 26881;
+const I915_PERF_IOCTL_CONFIG: u32 = 26882;
+const PRELIM_DRM_IOCTL_I915_GEM_VM_BIND: u32 = 3224396957;
+const PRELIM_DRM_IOCTL_I915_GEM_VM_UNBIND: u32 = 3224396956;
+const PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE: u32 = 3224396954;
+const PRELIM_DRM_IOCTL_I915_UUID_REGISTER: u32 = 3226494104;
+const PRELIM_DRM_IOCTL_I915_UUID_UNREGISTER: u32 = 3226494103;
+const DRM_IOCTL_SYNCOBJ_CREATE: u32 = 3221775551;
+const DRM_IOCTL_SYNCOBJ_DESTROY: u32 = 3221775552;
+const DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD: u32 = 3222299841;
+const DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE: u32 = 3222299842;
+const DRM_IOCTL_SYNCOBJ_WAIT: u32 = 3223348419;
+const DRM_IOCTL_SYNCOBJ_RESET: u32 = 3222299844;
+const DRM_IOCTL_SYNCOBJ_SIGNAL: u32 = 3222299845;
+const DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT: u32 = 3223872714;
+const DRM_IOCTL_SYNCOBJ_QUERY: u32 = 3222824139;
+const DRM_IOCTL_SYNCOBJ_TRANSFER: u32 = 3223348428;
+const DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL: u32 = 3222824141;
 
 #[derive(PartialEq)]
 enum Direction {
@@ -48,75 +94,230 @@ enum Direction {
     none,
 }
 
+// Which way the kernel passes a buffer pointed by an ioctl argument: read (In), written (Out) or
+// both (InOut). A buffer is only copied in the direction(s) its access needs.
+#[derive(PartialEq)]
+enum Access {
+    In,
+    Out,
+    InOut,
+}
+
+impl Access {
+    fn copies(&self, direction: &Direction) -> bool {
+        match direction {
+            Direction::t2u => self != &Access::Out,
+            Direction::u2t => self != &Access::In,
+            Direction::none => false,
+        }
+    }
+    // A pointer field to a buffer of this access, for the recording of the ioctls.
+    fn pointer<T>(&self, field: *const T) -> Pointer {
+        Pointer {
+            field: field as u64,
+            input: self.copies(&Direction::t2u),
+            output: self.copies(&Direction::u2t),
+        }
+    }
+}
+
+// Copy size bytes of a buffer, if its access needs it in this direction.
+fn copy_buffer(access: Access, direction: &Direction, src: u64, dst: u64, size: usize) {
+    if size > 0 && access.copies(direction) {
+        unsafe {
+            ptr::copy(src as *const u8, dst as *mut u8, size);
+        }
+    }
+}
+
 trait DeepCopy<T> {
-    fn alloc(&mut self, source: &T) -> Result<(), String>;
-    fn copy(&mut self, source: &T, direction: Direction) -> Result<(), String>;
-    fn free(&mut self) -> Result<(), String>;
+    fn alloc(&mut self, source: &T) -> Result<(), Error>;
+    fn copy(&mut self, source: &T, direction: Direction) -> Result<(), Error>;
+    // The pointer fields of the untrusted copy, and of the buffers it points to, for the recording of
+    // the ioctls. Called on the untrusted copy, before the ioctl.
+    fn pointers(&self, pointers: &mut Vec<Pointer>);
+    fn free(&mut self) -> Result<(), Error>;
+}
+
+// The buffer of `size` bytes a pointer field of an untrusted copy points to, if it's in a block
+// allocated by alloc(). The field may be unaligned, in the packed structures.
+fn pointee<T>(field: *const T, size: usize) -> Option<u64> {
+    let addr = unsafe { ptr::read_unaligned(field as *const u64) };
+    match allocation_of(addr as usize) {
+        Some((start, len)) if (addr as usize).checked_add(size)? <= start + len => Some(addr),
+        _ => None,
+    }
+}
+
+// The elements of the array of `count` T a pointer field points to.
+fn elements<T>(field: *const u64, count: usize) -> Vec<*const T> {
+    let size = mem::size_of::<T>();
+    match size.checked_mul(count).and_then(|len| pointee(field, len)) {
+        Some(addr) => (0..count).map(|i| (addr as usize + i * size) as *const T).collect(),
+        None => Vec::new(),
+    }
+}
+
+// The pointer fields of a chain of extensions, from the field pointing to its first node. f adds the
+// ones of each node.
+fn extension_pointers<F>(mut field: *const u64, pointers: &mut Vec<Pointer>, f: F)
+where
+    F: Fn(u64, &mut Vec<Pointer>),
+{
+    while let Some(node) = pointee(field, mem::size_of::<i915_user_extension>()) {
+        // The host may have made the chain loop.
+        if pointers.iter().any(|pointer| pointer.field == field as u64) {
+            break;
+        }
+        pointers.push(Access::In.pointer(field));
+        f(node, pointers);
+        field = unsafe { ptr::addr_of!((*(node as *const i915_user_extension)).next_extension) };
+    }
+}
+
+// A pointer field of an ioctl argument: (name, address, size).
+type PointerField = (&'static str, u64, usize);
+
+// The pointer fields of an ioctl argument which is shallow-copied by exec(). Those pointers aren't
+// translated to untrusted memory by the crate, so each of them must already point outside enclave.
+trait PointerFields {
+    // Pointers read by the kernel.
+    fn input_pointers(&self) -> Vec<PointerField> {
+        Vec::new()
+    }
+    // Pointers written back by the kernel.
+    fn output_pointers(&self) -> Vec<PointerField> {
+        Vec::new()
+    }
 }
 
 #[allow(unused_variables)]
-fn iterator<F>(mut ext_src: u64, mut ext_dst: &mut u64, f: F) -> Result<(), String>
+fn iterator<F>(mut ext_src: u64, mut ext_dst: &mut u64, f: F) -> Result<(), Error>
 where
-    F: Fn(u64, &mut u64) -> Result<(), String>,
+    F: Fn(u64, &mut u64) -> Result<(), Error>,
 {
     while ext_src != 0 {
-        f(ext_src, ext_dst).unwrap();
-        ext_src = unsafe { &*(ext_src as *const i915_user_extension) }.next_extension;
-        ext_dst = &mut unsafe { &mut *(*ext_dst as *mut i915_user_extension) }.next_extension;
+        f(ext_src, ext_dst)?;
+        ext_src = snapshot::<i915_user_extension>(ext_src).next_extension;
+        // The destination is allocated by alloc(), but its address is read back from untrusted memory.
+        let node = *ext_dst;
+        check_untrusted_ptr("extension", node, mem::size_of::<i915_user_extension>())?;
+        ext_dst = &mut unsafe { &mut *(node as *mut i915_user_extension) }.next_extension;
     }
     Ok(())
 }
 
-fn ioctl(fd: i32, cmd: &u32, arg: *const u8) -> i32 {
-    let mut ret: i32 = 0;
-    let mut status = sgx_status_t::SGX_ERROR_UNEXPECTED;
-    unsafe {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "occlum")] {
-                status = occlum_ocall_device_ioctl(
-                    &mut ret as *mut i32,
-                    fd,
-                    cmd.to_owned() as i32,
-                    arg as u64,
-                );
-            } else {
-                status = ocall_pxp_ioctl(
-                    &mut ret as *mut i32,
-                    fd,
-                    cmd.to_owned() as i32,
-                    arg as u64,
-                );
-            }
+// Take a snapshot of an untrusted structure. The host can change the original at any time, so only
+// the snapshot may be interpreted.
+fn snapshot<T>(addr: u64) -> T {
+    unsafe { ptr::read_volatile(addr as *const T) }
+}
+
+// Allocate untrusted memory for structures, zeroed so that a copy rejected half-way can still be
+// walked by free().
+fn alloc_zeroed(size: usize) -> Result<*mut u8, Error> {
+    let ptr = alloc(size)?;
+    if size > 0 {
+        unsafe {
+            ptr::write_bytes(ptr, 0, size);
         }
     }
-    assert!(status == sgx_status_t::SGX_SUCCESS);
-    ret
+    Ok(ptr)
+}
+
+// Reject a size or count reported back by the host that exceeds the one supplied by the enclave.
+fn check_untrusted_size(name: &str, reported: u64, supplied: u64) -> Result<(), Error> {
+    if reported > supplied {
+        return Err(Error::UntrustedValue(format!(
+            "{}: 0x{:x} exceeds 0x{:x}",
+            name, reported, supplied
+        )));
+    }
+    Ok(())
+}
+
+// Reject a pointer which would be handed to the host without translation, but points inside enclave.
+fn check_input_ptr(name: &str, ptr: u64, size: usize) -> Result<(), Error> {
+    if size > 0 && !transport().is_outside_enclave(ptr as *const u8, size) {
+        return Err(Error::InvalidArgument(format!(
+            "{}: can't hand TRUSTED pointer 0x{:x} to the host",
+            name, ptr
+        )));
+    }
+    Ok(())
 }
 
-fn exec<T>(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, String> {
+// Reject a pointer reported back by the host that doesn't point to `size` bytes outside enclave.
+fn check_untrusted_ptr(name: &str, ptr: u64, size: usize) -> Result<(), Error> {
+    if size > 0 && !transport().is_outside_enclave(ptr as *const u8, size) {
+        return Err(Error::UntrustedValue(format!(
+            "{}: 0x{:x} is not outside enclave",
+            name, ptr
+        )));
+    }
+    Ok(())
+}
+
+fn ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    transport().ioctl(fd, cmd.to_owned(), arg as u64)
+}
+
+fn exec<T: PointerFields>(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
     let size = mem::size_of::<T>();
     let ptr_t = arg as *mut T;
+    for (name, addr, len) in unsafe { &*ptr_t }.input_pointers() {
+        check_input_ptr(name, addr, len)?;
+    }
     let ptr_u = alloc(size)?;
-    unsafe { ptr::copy(ptr_t as *const u8, ptr_u as *mut u8, size); }
+    unsafe { ptr::copy(ptr_t as *const u8, ptr_u, size); }
     let ret = ioctl(fd, cmd, ptr_u);
-    unsafe { ptr::copy(ptr_u as *const u8, ptr_t as *mut u8, size); }
+    let reply = snapshot::<T>(ptr_u as u64);
     free(ptr_u, size)?;
-    Ok(ret)
+    if ret.is_ok() {
+        for (name, addr, len) in reply.output_pointers() {
+            check_untrusted_ptr(name, addr, len)?;
+        }
+    }
+    unsafe { ptr::copy(&reply as *const T as *const u8, ptr_t as *mut u8, size); }
+    ret
 }
 
-fn exec2<T: DeepCopy<T>>(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, String> {
+fn exec2<T: DeepCopy<T>>(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
     let size = mem::size_of::<T>();
-    let ptr_t = arg as *mut T;
+    let arg_t = unsafe { &mut *(arg as *mut T) };
     let ptr_u = alloc(size)?;
-    let arg_t = unsafe { &mut *(ptr_t as *mut T) };
-    let arg_u = unsafe { &mut *(ptr_u as *mut T) };
-    arg_u.alloc(arg_t)?;
-    arg_u.copy(arg_t, Direction::t2u)?;
-    let ret = ioctl(fd, cmd, ptr_u);
-    arg_t.copy(arg_u, Direction::u2t)?;
-    arg_u.free()?;
+    // Build the untrusted copy inside enclave and publish it at once, it's also the enclave's own
+    // record of what to free afterwards.
+    let mut stage: T = unsafe { mem::zeroed() };
+    let ret = exec_stage(fd, cmd, arg_t, &mut stage, ptr_u);
+    // Note: Release the untrusted memory on every path, also when the argument is rejected before
+    // the ioctl. The stage holds whatever alloc() got so far.
+    let freed = stage.free();
     free(ptr_u, size)?;
-    Ok(ret)
+    let ret = ret?;
+    freed.map(|_| ret)
+}
+
+fn exec_stage<T: DeepCopy<T>>(
+    fd: i32,
+    cmd: &u32,
+    arg_t: &mut T,
+    stage: &mut T,
+    ptr_u: *mut u8,
+) -> Result<i32, Error> {
+    stage.alloc(arg_t)?;
+    stage.copy(arg_t, Direction::t2u)?;
+    unsafe { ptr::copy(stage as *const T as *const u8, ptr_u, mem::size_of::<T>()); }
+    if trace::recording() {
+        let mut pointers = Vec::new();
+        unsafe { &*(ptr_u as *const T) }.pointers(&mut pointers);
+        trace::set_pointers(ptr_u as u64, pointers);
+    }
+    let ret = ioctl(fd, cmd, ptr_u);
+    // Note: Copy back even if the ioctl failed.
+    let reply = snapshot::<T>(ptr_u as u64);
+    let copied = arg_t.copy(&reply, Direction::u2t);
+    ret.and_then(|ret| copied.map(|_| ret))
 }
 
 #[repr(C)]
@@ -129,6 +330,7 @@ struct prelim_drm_i915_gem_memory_class_instance {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct prelim_drm_i915_gem_object_param {
     handle: u32,
     size: u32,
@@ -138,6 +340,7 @@ struct prelim_drm_i915_gem_object_param {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct i915_user_extension {
     next_extension: u64,
     name: u32,
@@ -145,9 +348,11 @@ struct i915_user_extension {
     rsvd: [u32; 4],
 }
 impl i915_user_extension {
-    fn init(&mut self) {
-        // Must init this next extension manually.
+    fn init(&mut self, name: u32) {
+        // Must init this next extension manually. The name is set at once, so that free() knows the
+        // node's layout even if the copy is rejected before reaching it.
         self.next_extension = 0;
+        self.name = name;
     }
     fn copy(&mut self, source: &i915_user_extension) {
         self.name = source.name;
@@ -158,51 +363,56 @@ impl i915_user_extension {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct prelim_drm_i915_gem_create_ext_setparam {
     base: i915_user_extension,
     param: prelim_drm_i915_gem_object_param,
 }
 impl prelim_drm_i915_gem_create_ext_setparam {
-    fn alloc(&mut self, source: &prelim_drm_i915_gem_create_ext_setparam) -> Result<(), String> {
+    fn alloc(&mut self, source: &prelim_drm_i915_gem_create_ext_setparam) -> Result<(), Error> {
         let size = mem::size_of::<prelim_drm_i915_gem_memory_class_instance>()
             .checked_mul(source.param.size as usize)
-            .ok_or(format!("mul error"))?;
+            .ok_or(Error::InvalidArgument(String::from("mul error")))?;
         self.param.data = alloc(size as usize)? as u64;
         Ok(())
     }
-    fn copy(&mut self, source: &prelim_drm_i915_gem_create_ext_setparam) -> Result<(), String> {
+    fn copy(&mut self, source: &prelim_drm_i915_gem_create_ext_setparam) -> Result<(), Error> {
+        let data = self.param.data;
         self.param.handle = source.param.handle;
         self.param.size = source.param.size;
         self.param.param = source.param.param;
         let size = mem::size_of::<prelim_drm_i915_gem_memory_class_instance>()
             .checked_mul(source.param.size as usize)
-            .ok_or(format!("mul error"))?;
-        unsafe {
-            ptr::copy(source.param.data as *const u8, self.param.data as *mut u8, size);
-        }
+            .ok_or(Error::InvalidArgument(String::from("mul error")))?;
+        check_untrusted_ptr("data", data, size)?;
+        copy_buffer(Access::In, &Direction::t2u, source.param.data, data, size);
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn free(&mut self) -> Result<(), Error> {
+        let param = snapshot::<prelim_drm_i915_gem_object_param>(&self.param as *const _ as u64);
         let size = mem::size_of::<prelim_drm_i915_gem_memory_class_instance>()
-            .checked_mul(self.param.size as usize)
-            .ok_or(format!("mul error"))?;
-        free(self.param.data as *mut u8, size)
+            .checked_mul(param.size as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))?;
+        free(param.data as *mut u8, size)
     }
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct prelim_drm_i915_gem_create_ext_vm_private {
     base: i915_user_extension,
     vm_id: u32,
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct prelim_drm_i915_gem_create_ext_protected_content {
     base: i915_user_extension,
     flags: u32,
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct prelim_drm_i915_gem_create_ext {
     size: u64,
     handle: u32,
@@ -210,34 +420,34 @@ struct prelim_drm_i915_gem_create_ext {
     extensions: u64,
 }
 impl prelim_drm_i915_gem_create_ext {
-    fn sizeof(name: u32) -> Result<usize, String> {
+    fn sizeof(name: u32) -> Result<usize, Error> {
         match name {
             1 => Ok(mem::size_of::<prelim_drm_i915_gem_create_ext_setparam>()),
             2 => Ok(mem::size_of::<prelim_drm_i915_gem_create_ext_vm_private>()),
             3 => Ok(mem::size_of::<
                 prelim_drm_i915_gem_create_ext_protected_content,
             >()),
-            _ => Err(format!("the name:{:?} is illegal !!!", name)),
+            _ => Err(Error::InvalidArgument(format!("the name:{:?} is illegal !!!", name))),
         }
     }
     fn iterator(
         &mut self,
         source: &prelim_drm_i915_gem_create_ext,
-        f: fn(u64, &mut u64) -> Result<(), String>,
-    ) -> Result<(), String> {
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let ext_src = source.extensions;
         let ext_dst = &mut self.extensions;
         iterator::<_>(ext_src, ext_dst, f)
     }
 }
 impl DeepCopy<prelim_drm_i915_gem_create_ext> for prelim_drm_i915_gem_create_ext {
-    fn alloc(&mut self, source: &prelim_drm_i915_gem_create_ext) -> Result<(), String> {
+    fn alloc(&mut self, source: &prelim_drm_i915_gem_create_ext) -> Result<(), Error> {
         self.extensions = 0;
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
             let size = Self::sizeof(ext_src.name & PRELIM_I915_USER_EXT_MASK)?;
-            *dst = alloc(size as usize)? as u64;
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.init();
+            *dst = alloc_zeroed(size)? as u64;
+            unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
             if ext_src.name & PRELIM_I915_USER_EXT_MASK == 1 {
                 let s = unsafe { &*(src as *const prelim_drm_i915_gem_create_ext_setparam) };
                 let d =
@@ -247,36 +457,49 @@ impl DeepCopy<prelim_drm_i915_gem_create_ext> for prelim_drm_i915_gem_create_ext
             Ok(())
         })
     }
-    fn copy(&mut self, source: &prelim_drm_i915_gem_create_ext, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &prelim_drm_i915_gem_create_ext, direction: Direction) -> Result<(), Error> {
         self.size = source.size;
         self.handle = source.handle;
         self.pad = source.pad;
+        if direction == Direction::u2t {
+            // The extensions are only read by the kernel, so never walk the host's copy of them.
+            return Ok(());
+        }
         // Copy extensions
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
-            let ext_dst = unsafe { &mut *(*dst as *mut i915_user_extension) };
+            let size = Self::sizeof(ext_src.name & PRELIM_I915_USER_EXT_MASK)?;
+            let node = *dst;
+            check_untrusted_ptr("extension", node, size)?;
+            let ext_dst = unsafe { &mut *(node as *mut i915_user_extension) };
             if ext_src.name & PRELIM_I915_USER_EXT_MASK == 1 {
                 // Deep copy
                 ext_dst.copy(ext_src);
                 let s = unsafe { &*(src as *const prelim_drm_i915_gem_create_ext_setparam) };
                 let d =
-                    unsafe { &mut *(*dst as *mut prelim_drm_i915_gem_create_ext_setparam) };
+                    unsafe { &mut *(node as *mut prelim_drm_i915_gem_create_ext_setparam) };
                 d.copy(s)?;
             } else {
                 let next = ext_dst.next_extension;
-                let size = Self::sizeof(ext_src.name & PRELIM_I915_USER_EXT_MASK)?;
-                unsafe {
-                    ptr::copy(src as *const u8, *dst as *mut u8, size);
-                }
+                copy_buffer(Access::In, &Direction::t2u, src, node, size);
                 ext_dst.next_extension = next;
             }
             Ok(())
         })
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        extension_pointers(&self.extensions, pointers, |node, pointers| {
+            if snapshot::<i915_user_extension>(node).name & PRELIM_I915_USER_EXT_MASK == 1 {
+                let setparam = node as *const prelim_drm_i915_gem_create_ext_setparam;
+                pointers.push(Access::In.pointer(unsafe { ptr::addr_of!((*setparam).param.data) }));
+            }
+        });
+    }
+    fn free(&mut self) -> Result<(), Error> {
         let mut ext = self.extensions;
         while ext != 0 {
-            let extension = unsafe { &*(ext as *const i915_user_extension) };
+            check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+            let extension = snapshot::<i915_user_extension>(ext);
             if extension.name & PRELIM_I915_USER_EXT_MASK == 1 {
                 unsafe { &mut *(ext as *mut prelim_drm_i915_gem_create_ext_setparam) }.free()?;
             }
@@ -294,6 +517,7 @@ impl DeepCopy<prelim_drm_i915_gem_create_ext> for prelim_drm_i915_gem_create_ext
 #[repr(C)]
 #[repr(packed)]
 #[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
 struct prelim_drm_i915_pxp_set_session_status_params {
     pxp_tag: u32,
     session_type: u32,
@@ -303,6 +527,7 @@ struct prelim_drm_i915_pxp_set_session_status_params {
 #[repr(C)]
 #[repr(packed)]
 #[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
 struct prelim_drm_i915_pxp_tee_io_message_params {
     msg_in: u64,
     msg_in_size: u32,
@@ -313,6 +538,7 @@ struct prelim_drm_i915_pxp_tee_io_message_params {
 #[repr(C)]
 #[repr(packed)]
 #[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
 struct prelim_drm_i915_pxp_query_tag {
     session_is_alive: u32,
     pxp_tag: u32,
@@ -320,25 +546,27 @@ struct prelim_drm_i915_pxp_query_tag {
 #[repr(C)]
 #[repr(packed)]
 #[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
 struct prelim_drm_i915_pxp_ops {
     action: u32,
     status: u32,
     params: u64,
 }
 impl prelim_drm_i915_pxp_ops {
-    fn sizeof(action: &u32) -> Result<usize, String> {
-        match action.to_owned() {
+    fn sizeof(action: u32) -> Result<usize, Error> {
+        match action {
             0 => Ok(mem::size_of::<prelim_drm_i915_pxp_set_session_status_params>()),
             1 => Ok(mem::size_of::<prelim_drm_i915_pxp_tee_io_message_params>()),
             2 => Ok(mem::size_of::<prelim_drm_i915_pxp_query_tag>()),
-            _ => Err(format!("the action is illegal !!!")),
+            _ => Err(Error::InvalidArgument(String::from("the action is illegal !!!"))),
         }
     }
 }
 impl DeepCopy<prelim_drm_i915_pxp_ops> for prelim_drm_i915_pxp_ops {
-    fn alloc(&mut self, source: &prelim_drm_i915_pxp_ops) -> Result<(), String> {
-        let size = Self::sizeof(&source.action)?;
-        self.params = alloc(size)? as u64;
+    fn alloc(&mut self, source: &prelim_drm_i915_pxp_ops) -> Result<(), Error> {
+        let size = Self::sizeof(source.action)?;
+        self.params = alloc_zeroed(size)? as u64;
+        self.action = source.action;
         if source.action == 1 {
             let s =
                 unsafe { &*(source.params as *const prelim_drm_i915_pxp_tee_io_message_params) };
@@ -349,77 +577,101 @@ impl DeepCopy<prelim_drm_i915_pxp_ops> for prelim_drm_i915_pxp_ops {
         }
         Ok(())
     }
-    fn copy(&mut self, source: &prelim_drm_i915_pxp_ops, direction: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &prelim_drm_i915_pxp_ops, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            // The action selects the layout of params, so the host must not change it.
+            if source.action != self.action {
+                let (reported, supplied) = (source.action, self.action);
+                return Err(Error::UntrustedValue(format!(
+                    "action: {:?} differs from {:?}",
+                    reported, supplied
+                )));
+            }
+            check_untrusted_ptr("params", source.params, Self::sizeof(source.action)?)?;
+        }
         self.action = source.action;
         self.status = source.status;
         // Deep copy
         match source.action {
-            0 | 2 => unsafe {
-                ptr::copy(
-                    source.params as *const u8,
-                    self.params as *mut u8,
-                    Self::sizeof(&source.action)?,
-                );
-            },
+            0 | 2 => copy_buffer(
+                Access::InOut,
+                &direction,
+                source.params,
+                self.params,
+                Self::sizeof(source.action)?,
+            ),
             1 => {
-                let s =
-                    unsafe { &*(source.params as *const prelim_drm_i915_pxp_tee_io_message_params) };
+                let s = snapshot::<prelim_drm_i915_pxp_tee_io_message_params>(source.params);
                 let d = unsafe {
                     &mut *(self.params as *mut prelim_drm_i915_pxp_tee_io_message_params)
                 };
-                d.msg_in_size = s.msg_in_size;
-                d.msg_out_buf_size = s.msg_out_buf_size;
-                d.msg_out_ret_size = s.msg_out_ret_size;
-                if s.msg_in_size > 0 {
-                    unsafe {
-                        ptr::copy(
-                            s.msg_in as *const u8,
-                            d.msg_in as *mut u8,
-                            s.msg_in_size as usize,
-                        );
-                    }
-                }
+                let (msg_in, msg_out) = (d.msg_in, d.msg_out);
                 if direction == Direction::t2u {
-                    // t2u
-                    if s.msg_out_buf_size > 0 {
-                        unsafe {
-                            ptr::copy(
-                                s.msg_out as *const u8,
-                                d.msg_out as *mut u8,
-                                s.msg_out_buf_size as usize,
-                            );
-                        }
-                    }
+                    // The buffers are allocated by alloc(), but read back from untrusted memory.
+                    check_untrusted_ptr("msg_in", msg_in, s.msg_in_size as usize)?;
+                    check_untrusted_ptr("msg_out", msg_out, s.msg_out_buf_size as usize)?;
                 } else {
-                    // u2t
-                    if s.msg_out_ret_size > 0 {
-                        unsafe {
-                            ptr::copy(
-                                s.msg_out as *const u8,
-                                d.msg_out as *mut u8,
-                                s.msg_out_ret_size as usize,
-                            );
-                        }
-                    }
+                    check_untrusted_size(
+                        "msg_in_size",
+                        s.msg_in_size as u64,
+                        d.msg_in_size as u64,
+                    )?;
+                    check_untrusted_size(
+                        "msg_out_buf_size",
+                        s.msg_out_buf_size as u64,
+                        d.msg_out_buf_size as u64,
+                    )?;
+                    check_untrusted_size(
+                        "msg_out_ret_size",
+                        s.msg_out_ret_size as u64,
+                        d.msg_out_buf_size as u64,
+                    )?;
+                    check_untrusted_ptr("msg_in", s.msg_in, s.msg_in_size as usize)?;
+                    check_untrusted_ptr("msg_out", s.msg_out, s.msg_out_ret_size as usize)?;
                 }
+                d.msg_in_size = s.msg_in_size;
+                d.msg_out_buf_size = s.msg_out_buf_size;
+                d.msg_out_ret_size = s.msg_out_ret_size;
+                copy_buffer(Access::In, &direction, s.msg_in, msg_in, s.msg_in_size as usize);
+                // Only the returned part of the output message is copied back.
+                copy_buffer(
+                    Access::Out,
+                    &direction,
+                    s.msg_out,
+                    msg_out,
+                    s.msg_out_ret_size as usize,
+                );
             }
             _ => error!("the action is illegal, so do nonthing"),
         }
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        let field = ptr::addr_of!(self.params);
+        pointers.push(Access::InOut.pointer(field));
+        if self.action != 1 {
+            return;
+        }
+        let size = mem::size_of::<prelim_drm_i915_pxp_tee_io_message_params>();
+        if let Some(params) = pointee(field, size) {
+            let params = params as *const prelim_drm_i915_pxp_tee_io_message_params;
+            pointers.push(Access::In.pointer(unsafe { ptr::addr_of!((*params).msg_in) }));
+            pointers.push(Access::Out.pointer(unsafe { ptr::addr_of!((*params).msg_out) }));
+        }
+    }
+    fn free(&mut self) -> Result<(), Error> {
         if self.action == 1 {
-            let param =
-                unsafe { &*(self.params as *mut prelim_drm_i915_pxp_tee_io_message_params) };
+            let param = snapshot::<prelim_drm_i915_pxp_tee_io_message_params>(self.params);
             free(param.msg_in as *mut u8, param.msg_in_size as usize)?;
             free(param.msg_out as *mut u8, param.msg_out_buf_size as usize)?;
         }
-        free(self.params as *mut u8, Self::sizeof(&self.action)?)
+        free(self.params as *mut u8, Self::sizeof(self.action)?)
     }
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_query_item {
     query_id: u64,
     length: i32,
@@ -427,68 +679,79 @@ struct drm_i915_query_item {
     data_ptr: u64,
 }
 impl drm_i915_query_item {
-    fn alloc(&mut self, source: &drm_i915_query_item) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_query_item) -> Result<(), Error> {
         self.data_ptr = alloc(source.length as usize)? as u64;
         Ok(())
     }
-    fn copy(&mut self, source: &drm_i915_query_item) {
+    fn copy(&mut self, source: &drm_i915_query_item, direction: &Direction) -> Result<(), Error> {
+        let data_ptr = self.data_ptr;
+        if direction == &Direction::t2u {
+            // The buffer is allocated by alloc(), but its address is read back from untrusted memory.
+            check_untrusted_ptr("data_ptr", data_ptr, source.length.max(0) as usize)?;
+        }
         self.query_id = source.query_id;
         self.length = source.length;
         self.flags = source.flags;
-        if source.length > 0 {
-            unsafe {
-                ptr::copy(
-                    source.data_ptr as *const u8,
-                    self.data_ptr as *mut u8,
-                    source.length as usize,
-                );
-            }
-        }
+        // Some items read their input from the buffer, e.g. the uuid of a perf config.
+        copy_buffer(
+            Access::InOut,
+            direction,
+            source.data_ptr,
+            data_ptr,
+            source.length.max(0) as usize,
+        );
+        Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
-        free(self.data_ptr as *mut u8, self.length as usize)
+    fn free(&mut self) -> Result<(), Error> {
+        let item = snapshot::<drm_i915_query_item>(self as *const _ as u64);
+        free(item.data_ptr as *mut u8, item.length as usize)
     }
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 pub struct drm_i915_query {
     num_items: u32,
     flags: u32,
     items_ptr: u64,
 }
 impl drm_i915_query {
-    fn items_size(num_items: &u32) -> Result<usize, String> {
+    fn items_size(num_items: &u32) -> Result<usize, Error> {
         mem::size_of::<drm_i915_query_item>()
             .checked_mul(num_items.to_owned() as usize)
-            .ok_or(format!("mul error"))
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
     }
     fn iterator(
         &mut self,
         source: &drm_i915_query,
         direction: &Direction,
-        f: fn(&drm_i915_query_item, &mut drm_i915_query_item, direction: &Direction) -> Result<(), String>,
-    ) -> Result<(), String> {
+        f: fn(&drm_i915_query_item, &mut drm_i915_query_item, direction: &Direction) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let offset = mem::size_of::<drm_i915_query_item>();
         for i in 0..source.num_items {
-            let s = unsafe {
-                &*((source.items_ptr as *mut u8)
-                    .add(offset.checked_mul(i as usize).ok_or(format!("mul error"))?)
-                    as *const drm_i915_query_item)
-            };
+            let s = snapshot::<drm_i915_query_item>(
+                source.items_ptr
+                    + offset
+                        .checked_mul(i as usize)
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))? as u64,
+            );
             let d = unsafe {
-                &mut *((self.items_ptr as *mut u8)
-                    .add(offset.checked_mul(i as usize).ok_or(format!("mul error"))?)
-                    as *mut drm_i915_query_item)
+                &mut *((self.items_ptr as *mut u8).add(
+                    offset
+                        .checked_mul(i as usize)
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?,
+                ) as *mut drm_i915_query_item)
             };
-            f(s, d, direction)?;
+            f(&s, d, direction)?;
         }
         Ok(())
     }
 }
 impl DeepCopy<drm_i915_query> for drm_i915_query {
-    fn alloc(&mut self, source: &drm_i915_query) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_query) -> Result<(), Error> {
         let size = Self::items_size(&source.num_items)?;
-        self.items_ptr = alloc(size)? as u64;
+        self.items_ptr = alloc_zeroed(size)? as u64;
+        self.num_items = source.num_items;
         self.iterator(
             source,
             &Direction::none,
@@ -497,31 +760,49 @@ impl DeepCopy<drm_i915_query> for drm_i915_query {
             },
         )
     }
-    fn copy(&mut self, source: &drm_i915_query, direction: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_query, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            check_untrusted_size("num_items", source.num_items as u64, self.num_items as u64)?;
+            check_untrusted_ptr(
+                "items_ptr",
+                source.items_ptr,
+                Self::items_size(&source.num_items)?,
+            )?;
+        }
         self.num_items = source.num_items;
         self.flags = source.flags;
         // Deep copy
         self.iterator(
             source,
             &direction,
-            |src: &drm_i915_query_item, dst: &mut drm_i915_query_item, direction: &Direction| -> Result<(), String> {
-                if direction == &Direction::u2t && dst.length == 0 {
-                    dst.length = src.length;
-                    return Ok(());
+            |src: &drm_i915_query_item, dst: &mut drm_i915_query_item, direction: &Direction| -> Result<(), Error> {
+                if direction == &Direction::u2t {
+                    if dst.length == 0 {
+                        dst.length = src.length;
+                        return Ok(());
+                    }
+                    // The kernel may shrink the length or report an error with a negative one.
+                    check_untrusted_size("length", src.length.max(0) as u64, dst.length as u64)?;
+                    check_untrusted_ptr("data_ptr", src.data_ptr, src.length.max(0) as usize)?;
                 }
-                dst.copy(src);
-                Ok(())
+                dst.copy(src, direction)
             }
         )
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::InOut.pointer(&self.items_ptr));
+        for item in elements::<drm_i915_query_item>(&self.items_ptr, self.num_items as usize) {
+            pointers.push(Access::InOut.pointer(unsafe { ptr::addr_of!((*item).data_ptr) }));
+        }
+    }
+    fn free(&mut self) -> Result<(), Error> {
         let size = Self::items_size(&self.num_items)?;
         for i in 0..self.num_items {
             let item = unsafe {
                 &mut *((self.items_ptr as *mut u8).add(
                     mem::size_of::<drm_i915_query_item>()
                         .checked_mul(i as usize)
-                        .ok_or(format!("mul error"))?,
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?,
                 ) as *mut drm_i915_query_item)
             };
             item.free()?;
@@ -532,6 +813,7 @@ impl DeepCopy<drm_i915_query> for drm_i915_query {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 pub struct drm_i915_gem_context_param {
     ctx_id: u32,
     size: u32,
@@ -539,60 +821,242 @@ pub struct drm_i915_gem_context_param {
     value: u64,
 }
 impl drm_i915_gem_context_param {
-    fn alloc(
-        &mut self,
-        source: &drm_i915_gem_context_param,
-        f: fn(src: u64, dst: u64) -> Result<(), String>,
-    ) -> Result<(), String> {
-        self.value = alloc(source.size as usize)? as u64;
-        f(self.value, source.value)
+    // The size of the buffer pointed by value, or None if value is passed as is (VM, PERSISTENCE,
+    // PRIORITY, ...). A size of 0 only queries the size needed by the kernel.
+    fn value_size(param: u64, size: u32) -> Result<Option<usize>, Error> {
+        if size == 0 {
+            return Ok(None);
+        }
+        match param {
+            I915_CONTEXT_PARAM_SSEU => {
+                if (size as usize) < mem::size_of::<drm_i915_gem_context_param_sseu>() {
+                    return Err(Error::InvalidArgument(format!("sseu size:{:?} is illegal !!!", size)));
+                }
+                Ok(Some(size as usize))
+            }
+            I915_CONTEXT_PARAM_ENGINES => {
+                if (size as usize) < mem::size_of::<i915_context_param_engines>()
+                    || !(size as usize - mem::size_of::<i915_context_param_engines>())
+                        .is_multiple_of(mem::size_of::<i915_engine_class_instance>())
+                {
+                    return Err(Error::InvalidArgument(format!("engines size:{:?} is illegal !!!", size)));
+                }
+                Ok(Some(size as usize))
+            }
+            // I915_CONTEXT_PARAM_BAN_PERIOD ... I915_CONTEXT_PARAM_PROTECTED_CONTENT
+            0x1..=0x6 | 0x8 | 0x9 | 0xb..=0xd => Ok(None),
+            // Unknown params are copied as a blob.
+            _ => Ok(Some(size as usize)),
+        }
     }
-    fn copy(
-        &mut self,
-        source: &drm_i915_gem_context_param,
-        f: fn(dst: u64, src: u64) -> Result<(), String>,
-    ) -> Result<(), String> {
+    fn alloc_value(&mut self, source: &drm_i915_gem_context_param) -> Result<(), Error> {
+        let size = match Self::value_size(source.param, source.size)? {
+            Some(size) => size,
+            None => {
+                self.value = crate::memory::PTR_NULL;
+                return Ok(());
+            }
+        };
+        // free_value() needs them to find the size, and the extensions of the engines.
+        self.param = source.param;
+        self.size = source.size;
+        self.value = alloc(size)? as u64;
+        if source.param == I915_CONTEXT_PARAM_ENGINES {
+            let engines_src = unsafe { &*(source.value as *const i915_context_param_engines) };
+            let engines_dst = unsafe { &mut *(self.value as *mut i915_context_param_engines) };
+            engines_dst.extensions = 0;
+            engines_dst.alloc(engines_src)?;
+        }
+        Ok(())
+    }
+    // t2u only, self may be in untrusted memory.
+    fn copy_value(&mut self, source: &drm_i915_gem_context_param) -> Result<(), Error> {
+        let value = self.value;
         self.ctx_id = source.ctx_id;
         self.size = source.size;
         self.param = source.param;
-        if source.size > 0 {
-            unsafe {
-                ptr::copy(
-                    source.value as *const u8,
-                    self.value as *mut u8,
-                    source.size as usize,
-                );
+        let size = match Self::value_size(source.param, source.size)? {
+            Some(size) => size,
+            None => {
+                self.value = source.value;
+                return Ok(());
             }
+        };
+        check_untrusted_ptr("value", value, size)?;
+        if source.param == I915_CONTEXT_PARAM_ENGINES {
+            // Keep the untrusted extensions allocated by alloc_value(), only the engines follow.
+            let header = mem::size_of::<i915_context_param_engines>();
+            copy_buffer(
+                Access::In,
+                &Direction::t2u,
+                source.value + header as u64,
+                value + header as u64,
+                size - header,
+            );
+            let engines_src = unsafe { &*(source.value as *const i915_context_param_engines) };
+            let engines_dst = unsafe { &mut *(value as *mut i915_context_param_engines) };
+            engines_dst.copy(engines_src)?;
         } else {
-            self.value = source.value;
+            copy_buffer(Access::In, &Direction::t2u, source.value, value, size);
+        }
+        Ok(())
+    }
+    // The pointer fields of the value, which is only read by the kernel.
+    fn value_pointers(&self, pointers: &mut Vec<Pointer>) {
+        let size = match Self::value_size(self.param, self.size) {
+            Ok(Some(size)) => size,
+            _ => return,
+        };
+        pointers.push(Access::In.pointer(&self.value));
+        if self.param == I915_CONTEXT_PARAM_ENGINES && pointee(&self.value, size).is_some() {
+            let engines = self.value as *const i915_context_param_engines;
+            extension_pointers(unsafe { ptr::addr_of!((*engines).extensions) }, pointers, |_, _| {});
         }
-        f(self.value, source.value)
     }
-    fn free(&mut self, f: fn(dst: u64) -> Result<(), String>) -> Result<(), String> {
-        f(self.value)?;
-        free(self.value as *mut u8, self.size as usize)
+    // self must be trusted or a snapshot.
+    fn free_value(&self) -> Result<(), Error> {
+        let size = match Self::value_size(self.param, self.size)? {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        if self.param == I915_CONTEXT_PARAM_ENGINES {
+            check_untrusted_ptr("value", self.value, size)?;
+            unsafe { &mut *(self.value as *mut i915_context_param_engines) }.free()?;
+        }
+        free(self.value as *mut u8, size)
+    }
+    // u2t, the value is only copied back if the kernel writes it.
+    fn copy_reply(&mut self, source: &drm_i915_gem_context_param, access: Access) -> Result<(), Error> {
+        let size = match Self::value_size(self.param, self.size)? {
+            Some(size) => size,
+            None => {
+                // Special case: the size is queried or value is returned as is.
+                if self.size == 0 {
+                    self.size = source.size;
+                }
+                self.value = source.value;
+                return Ok(());
+            }
+        };
+        check_untrusted_size("size", source.size as u64, self.size as u64)?;
+        check_untrusted_ptr("value", source.value, size)?;
+        // The engines are returned with no extensions, never hand over the untrusted ones.
+        let header = if self.param == I915_CONTEXT_PARAM_ENGINES {
+            mem::size_of::<i915_context_param_engines>()
+        } else {
+            0
+        };
+        let len = (source.size as usize)
+            .checked_sub(header)
+            .ok_or(Error::UntrustedValue(format!("size: 0x{:x} is too small", source.size)))?;
+        copy_buffer(
+            access,
+            &Direction::u2t,
+            source.value + header as u64,
+            self.value + header as u64,
+            len,
+        );
+        self.size = source.size;
+        Ok(())
     }
 }
 impl DeepCopy<drm_i915_gem_context_param> for drm_i915_gem_context_param {
-    fn alloc(&mut self, source: &drm_i915_gem_context_param) -> Result<(), String> {
-        self.alloc(source, |_src, _dst| -> Result<(), String> { Ok(()) })
-    }
-    fn copy(&mut self, source: &drm_i915_gem_context_param, direction: Direction) -> Result<(), String> {
-        if direction == Direction::u2t && self.size == 0 {
-            // Special case
-            self.size = source.size;
-            self.value = source.value;
+    fn alloc(&mut self, source: &drm_i915_gem_context_param) -> Result<(), Error> {
+        self.alloc_value(source)
+    }
+    fn copy(&mut self, source: &drm_i915_gem_context_param, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::t2u {
+            return self.copy_value(source);
+        }
+        // DRM_IOCTL_I915_GEM_CONTEXT_SETPARAM: the value is only read by the kernel.
+        self.copy_reply(source, Access::In)
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        self.value_pointers(pointers)
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        self.free_value()
+    }
+}
+// DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM: the buffer pointed by value is only written by the kernel,
+// so the one of the application is neither read nor walked, even for the engines. The exception is
+// the sseu, whose engine and flags select what the kernel reports.
+#[repr(transparent)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_context_getparam(drm_i915_gem_context_param);
+impl drm_i915_gem_context_getparam {
+    fn access(param: u64) -> Access {
+        if param == I915_CONTEXT_PARAM_SSEU {
+            Access::InOut
+        } else {
+            Access::Out
+        }
+    }
+}
+impl DeepCopy<drm_i915_gem_context_getparam> for drm_i915_gem_context_getparam {
+    fn alloc(&mut self, source: &drm_i915_gem_context_getparam) -> Result<(), Error> {
+        let param = &mut self.0;
+        param.param = source.0.param;
+        param.size = source.0.size;
+        param.value = match drm_i915_gem_context_param::value_size(param.param, param.size)? {
+            Some(size) => alloc_zeroed(size)? as u64,
+            None => crate::memory::PTR_NULL,
+        };
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_i915_gem_context_getparam, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            self.0.copy_reply(&source.0, Self::access(self.0.param))?;
+            if self.0.param == I915_CONTEXT_PARAM_ENGINES && self.0.value != 0 {
+                unsafe { &mut *(self.0.value as *mut i915_context_param_engines) }.extensions = 0;
+            }
             return Ok(());
         }
-        self.copy(source, |_dst, _src| -> Result<(), String> { Ok(()) })
+        let (param, source) = (&mut self.0, &source.0);
+        param.ctx_id = source.ctx_id;
+        match drm_i915_gem_context_param::value_size(source.param, source.size)? {
+            Some(size) => copy_buffer(
+                Self::access(source.param),
+                &direction,
+                source.value,
+                param.value,
+                size,
+            ),
+            None => param.value = source.value,
+        }
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        if let Ok(Some(_)) = drm_i915_gem_context_param::value_size(self.0.param, self.0.size) {
+            pointers.push(Self::access(self.0.param).pointer(&self.0.value));
+        }
     }
-    fn free(&mut self) -> Result<(), String> {
-        self.free(|_dst| -> Result<(), String> { Ok(()) })
+    fn free(&mut self) -> Result<(), Error> {
+        let param = &self.0;
+        match drm_i915_gem_context_param::value_size(param.param, param.size)? {
+            Some(size) => free(param.value as *mut u8, size),
+            None => Ok(()),
+        }
     }
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_context_param_sseu {
+    engine: i915_engine_class_instance,
+    flags: u32,
+    slice_mask: u64,
+    subslice_mask: u64,
+    min_eus_per_subslice: u16,
+    max_eus_per_subslice: u16,
+    rsvd: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
 pub struct drm_version {
     version_major: i32,
     version_minor: i32,
@@ -607,41 +1071,35 @@ pub struct drm_version {
 macro_rules! drm_version_copy {
     ($src:expr, $dst:expr, $size:ident, $ptr:ident, $direction:expr) => {
         if $direction == Direction::t2u {
-            // t2u
+            // t2u: the string is only written by the kernel.
             $dst.$size = $src.$size;
-            if $src.$size > 0 {
-                unsafe {
-                    ptr::copy(
-                        $src.$ptr as *const u8,
-                        $dst.$ptr as *mut u8,
-                        $src.$size as usize,
-                    );
-                }
-            }
         } else {
             // u2t
             if $dst.$size == 0 {
                 $dst.$size = $src.$size;
             } else {
-                unsafe {
-                    ptr::copy(
-                        $src.$ptr as *const u8,
-                        $dst.$ptr as *mut u8,
-                        $dst.$size as usize,
-                    );
-                }
+                check_untrusted_ptr(stringify!($ptr), $src.$ptr as u64, $dst.$size as usize)?;
+                copy_buffer(
+                    Access::Out,
+                    &$direction,
+                    $src.$ptr as u64,
+                    $dst.$ptr as u64,
+                    $dst.$size as usize,
+                );
+                // Like drm_copy_field(), the full length is reported even if the string was cut.
+                $dst.$size = $src.$size;
             }
         }
     }
 }
 impl DeepCopy<drm_version> for drm_version {
-    fn alloc(&mut self, source: &drm_version) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_version) -> Result<(), Error> {
         self.name = alloc(source.name_len as usize)?;
         self.date = alloc(source.date_len as usize)?;
         self.desc = alloc(source.desc_len as usize)?;
         Ok(())
     }
-    fn copy(&mut self, source: &drm_version, direction: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_version, direction: Direction) -> Result<(), Error> {
         self.version_major = source.version_major;
         self.version_minor = source.version_minor;
         self.version_patchlevel = source.version_patchlevel;
@@ -652,7 +1110,12 @@ impl DeepCopy<drm_version> for drm_version {
         drm_version_copy!(source, self, desc_len, desc, direction);
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::Out.pointer(&self.name));
+        pointers.push(Access::Out.pointer(&self.date));
+        pointers.push(Access::Out.pointer(&self.desc));
+    }
+    fn free(&mut self) -> Result<(), Error> {
         free(self.name as *mut u8, self.name_len as usize)?;
         free(self.date as *mut u8, self.date_len as usize)?;
         free(self.desc as *mut u8, self.desc_len as usize)?;
@@ -662,36 +1125,124 @@ impl DeepCopy<drm_version> for drm_version {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_unique {
+    unique_len: u64,
+    unique: *const u8,
+}
+impl DeepCopy<drm_unique> for drm_unique {
+    fn alloc(&mut self, source: &drm_unique) -> Result<(), Error> {
+        self.unique = alloc(source.unique_len as usize)?;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_unique, direction: Direction) -> Result<(), Error> {
+        // Deep copy
+        // Note: Must check self.unique_len value.
+        drm_version_copy!(source, self, unique_len, unique, direction);
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::Out.pointer(&self.unique));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(self.unique as *mut u8, self.unique_len as usize)
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_auth {
     magic: u32,
 }
+impl PointerFields for drm_auth {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_get_cap {
+    capability: u64,
+    value: u64,
+}
+impl PointerFields for drm_get_cap {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_set_client_cap {
+    capability: u64,
+    value: u64,
+}
+impl PointerFields for drm_set_client_cap {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_set_version {
+    drm_di_major: i32,
+    drm_di_minor: i32,
+    drm_dd_major: i32,
+    drm_dd_minor: i32,
+}
+impl PointerFields for drm_set_version {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_gem_flink {
+    handle: u32,
+    name: u32,
+}
+impl PointerFields for drm_gem_flink {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_gem_open {
+    name: u32,
+    handle: u32,
+    size: u64,
+}
+impl PointerFields for drm_gem_open {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_getparam {
     param: u32,
     value: *mut i32,
 }
 impl DeepCopy<drm_i915_getparam> for drm_i915_getparam {
-    fn alloc(&mut self, _: &drm_i915_getparam) -> Result<(), String> {
+    fn alloc(&mut self, _: &drm_i915_getparam) -> Result<(), Error> {
         self.value = alloc(mem::size_of::<i32>())? as *mut i32;
         Ok(())
     }
-    fn copy(&mut self, source: &drm_i915_getparam, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_getparam, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            check_untrusted_ptr("value", source.value as u64, mem::size_of::<i32>())?;
+        }
         self.param = source.param;
         // Deep copy
-        unsafe {
-            *self.value = *source.value;
-        }
+        copy_buffer(
+            Access::Out,
+            &direction,
+            source.value as u64,
+            self.value as u64,
+            mem::size_of::<i32>(),
+        );
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::Out.pointer(&self.value));
+    }
+    fn free(&mut self) -> Result<(), Error> {
         free(self.value as *mut u8, mem::size_of::<i32>())
     }
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct i915_engine_class_instance {
     engine_class: u16,
     engine_instance: u16,
@@ -734,12 +1285,13 @@ struct i915_context_engines_load_balance {
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct i915_context_param_engines {
     extensions: u64,
     //engines: [i915_engine_class_instance; 0],
 }
 impl i915_context_param_engines {
-    fn sizeof(addr: u64) -> Result<usize, String> {
+    fn sizeof(addr: u64) -> Result<usize, Error> {
         let ext = unsafe { &*(addr as *const i915_user_extension) };
         match ext.name & PRELIM_I915_USER_EXT_MASK {
             0 => {
@@ -747,7 +1299,7 @@ impl i915_context_param_engines {
                 let size = mem::size_of::<i915_context_engines_load_balance>()
                     + mem::size_of::<i915_engine_class_instance>()
                         .checked_mul(t.num_siblings as usize)
-                        .ok_or(format!("mul error"))?;
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?;
                 Ok(size)
             }
             1 => {
@@ -755,7 +1307,7 @@ impl i915_context_param_engines {
                 let size = mem::size_of::<i915_context_engines_bond>()
                     + mem::size_of::<i915_engine_class_instance>()
                         .checked_mul(t.num_bonds as usize)
-                        .ok_or(format!("mul error"))?;
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?;
                 Ok(size)
             }
             2 | 3 => {
@@ -763,47 +1315,49 @@ impl i915_context_param_engines {
                 let size = mem::size_of::<i915_context_engines_parallel_submit>()
                     + mem::size_of::<i915_engine_class_instance>()
                         .checked_mul(t.num_siblings as usize)
-                        .ok_or(format!("mul error"))?
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?
                         .checked_mul(t.width as usize)
-                        .ok_or(format!("mul error"))?;
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?;
                 Ok(size)
             }
-            _ => Err(format!("base.name is not supported")),
+            _ => Err(Error::InvalidArgument(String::from("base.name is not supported"))),
         }
     }
     fn iterator(
         &mut self,
         source: &i915_context_param_engines,
-        f: fn(u64, &mut u64) -> Result<(), String>,
-    ) -> Result<(), String> {
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let ext_dst = &mut self.extensions;
         let ext_src = source.extensions;
         iterator::<_>(ext_src, ext_dst, f)
     }
-    fn alloc(&mut self, source: &i915_context_param_engines) -> Result<(), String> {
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+    fn alloc(&mut self, source: &i915_context_param_engines) -> Result<(), Error> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
+            let ext_src = unsafe { &*(src as *const i915_user_extension) };
             let size = Self::sizeof(src)?;
-            *dst = alloc(size)? as u64;
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.init();
+            *dst = alloc_zeroed(size)? as u64;
+            unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
             Ok(())
         })
     }
-    fn copy(&mut self, source: &i915_context_param_engines) -> Result<(), String> {
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
-            let next = unsafe { &*(*dst as *const i915_user_extension) }.next_extension;
+    fn copy(&mut self, source: &i915_context_param_engines) -> Result<(), Error> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             // Deep copy extension
             let size = Self::sizeof(src)?;
-            unsafe {
-                ptr::copy(src as *const u8, *dst as *mut u8, size);
-            }
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.next_extension = next;
+            let node = *dst;
+            check_untrusted_ptr("extension", node, size)?;
+            let next = unsafe { &*(node as *const i915_user_extension) }.next_extension;
+            copy_buffer(Access::In, &Direction::t2u, src, node, size);
+            unsafe { &mut *(node as *mut i915_user_extension) }.next_extension = next;
             Ok(())
         })
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn free(&mut self) -> Result<(), Error> {
         let mut ext = self.extensions;
         while ext != 0 {
-            let next = unsafe { &*(ext as *const i915_user_extension) }.next_extension;
+            check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+            let next = snapshot::<i915_user_extension>(ext).next_extension;
             let size = Self::sizeof(ext)?;
             free(ext as *mut u8, size)?;
             ext = next;
@@ -813,109 +1367,68 @@ impl i915_context_param_engines {
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_context_create_ext_setparam {
     base: i915_user_extension,
     param: drm_i915_gem_context_param,
 }
 impl drm_i915_gem_context_create_ext_setparam {
-    fn alloc(&mut self, source: &drm_i915_gem_context_create_ext_setparam) -> Result<(), String> {
-        if source.param.size > 0 {
-            self.param.value = alloc(source.param.size as usize)? as u64;
-            if source.param.param == I915_CONTEXT_PARAM_ENGINES {
-                info!("I915_CONTEXT_PARAM_ENGINES is called ...");
-                let context_src = unsafe {
-                    &mut *(source.param.value as *mut drm_i915_gem_context_param)
-                };
-                let context_dst = unsafe {
-                    &mut *(self.param.value as *mut drm_i915_gem_context_param)
-                };
-                context_dst.alloc(context_src, |src, dst| -> Result<(), String> {
-                    let engines_src = unsafe { &*(src as *const i915_context_param_engines) };
-                    let engines_dst =
-                        unsafe { &mut *(dst as *mut i915_context_param_engines) };
-                    engines_dst.alloc(engines_src)
-                })?;
-            }
-        } else {
-            self.param.value = crate::memory::PTR_NULL;
-        }
-        Ok(())
+    fn alloc(&mut self, source: &drm_i915_gem_context_create_ext_setparam) -> Result<(), Error> {
+        self.param.alloc_value(&source.param)
     }
-    fn copy(&mut self, source: &drm_i915_gem_context_create_ext_setparam) -> Result<(), String> {
-        self.param.ctx_id = source.param.ctx_id;
-        self.param.size = source.param.size;
-        self.param.param = source.param.param;
-        if source.param.size > 0 {
-            unsafe {
-                ptr::copy(
-                    source.param.value as *const u8,
-                    self.param.value as *mut u8,
-                    source.param.size as usize,
-                );
-            }
-            if source.param.param == I915_CONTEXT_PARAM_ENGINES {
-                let context_src = unsafe {
-                    &*(source.param.value as *const drm_i915_gem_context_param)
-                };
-                let context_dst = unsafe {
-                    &mut *(self.param.value as *mut drm_i915_gem_context_param)
-                };
-                context_dst.copy(context_src, |src, dst| -> Result<(), String> {
-                    let engines_src = unsafe { &*(src as *const i915_context_param_engines) };
-                    let engines_dst =
-                        unsafe { &mut *(dst as *mut i915_context_param_engines) };
-                    engines_dst.copy(engines_src)
-                })?;
-            }
-        }
-        Ok(())
+    fn copy(&mut self, source: &drm_i915_gem_context_create_ext_setparam) -> Result<(), Error> {
+        self.param.copy_value(&source.param)
     }
-    fn free(&mut self) -> Result<(), String> {
-        let context =
-            unsafe { &mut *(self.param.value as *mut drm_i915_gem_context_param) };
-        context.free(|dst| -> Result<(), String> {
-            let engines = unsafe { &mut *(dst as *mut i915_context_param_engines) };
-            engines.free()
-        })?;
-
-        free(self.param.value as *mut u8, self.param.size as usize)?;
-        Ok(())
+    fn free(&mut self) -> Result<(), Error> {
+        snapshot::<drm_i915_gem_context_param>(&self.param as *const _ as u64).free_value()
     }
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_context_create_ext_clone {
+    base: i915_user_extension,
+    clone_id: u32,
+    flags: u32,
+    rsvd: u64,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_context_create_ext {
     ctx_id: u32,
     flags: u32,
     extensions: u64,
 }
 impl drm_i915_gem_context_create_ext {
-    fn sizeof(name: u32) -> Result<usize, String> {
+    fn sizeof(name: u32) -> Result<usize, Error> {
         match name {
             // I915_CONTEXT_CREATE_EXT_SETPARAM
             0 => Ok(mem::size_of::<drm_i915_gem_context_create_ext_setparam>()),
-            _ => Err(format!("name is not supported")),
+            // I915_CONTEXT_CREATE_EXT_CLONE
+            1 => Ok(mem::size_of::<drm_i915_gem_context_create_ext_clone>()),
+            _ => Err(Error::InvalidArgument(String::from("name is not supported"))),
         }
     }
     fn iterator(
         &mut self,
         source: &drm_i915_gem_context_create_ext,
-        f: fn(u64, &mut u64) -> Result<(), String>,
-    ) -> Result<(), String> {
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let ext_dst = &mut self.extensions;
         let ext_src = source.extensions;
         iterator::<_>(ext_src, ext_dst, f)
     }
 }
 impl DeepCopy<drm_i915_gem_context_create_ext> for drm_i915_gem_context_create_ext {
-    fn alloc(&mut self, source: &drm_i915_gem_context_create_ext) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_gem_context_create_ext) -> Result<(), Error> {
         //info!("drm_i915_gem_context_create_ext: extensions:{:?}", source.extensions);
         self.extensions = 0;
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
             let size = Self::sizeof(ext_src.name)?;
-            *dst = alloc(size)? as u64;
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.init();
+            *dst = alloc_zeroed(size)? as u64;
+            unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
             if ext_src.name == 0 {
                 let param_src = unsafe { &*(src as *const drm_i915_gem_context_create_ext_setparam) };
                 let param_dst =
@@ -925,28 +1438,49 @@ impl DeepCopy<drm_i915_gem_context_create_ext> for drm_i915_gem_context_create_e
             Ok(())
         })
     }
-    fn copy(&mut self, source: &drm_i915_gem_context_create_ext, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_gem_context_create_ext, direction: Direction) -> Result<(), Error> {
         self.ctx_id = source.ctx_id;
         self.flags = source.flags;
+        if direction == Direction::u2t {
+            // The extensions are only read by the kernel, so never walk the host's copy of them.
+            return Ok(());
+        }
         // Extensions
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
-            let ext_dst = unsafe { &mut *(*dst as *mut i915_user_extension) };
+            let node = *dst;
+            check_untrusted_ptr("extension", node, Self::sizeof(ext_src.name)?)?;
+            let ext_dst = unsafe { &mut *(node as *mut i915_user_extension) };
             ext_dst.copy(ext_src);
             if ext_src.name == 0 {
                 // Deep copy extension
                 let s = unsafe { &*(src as *mut drm_i915_gem_context_create_ext_setparam) };
                 let d =
-                    unsafe { &mut *(*dst as *mut drm_i915_gem_context_create_ext_setparam) };
+                    unsafe { &mut *(node as *mut drm_i915_gem_context_create_ext_setparam) };
                 d.copy(s)?;
+            } else {
+                let s = unsafe { &*(src as *const drm_i915_gem_context_create_ext_clone) };
+                let d = unsafe { &mut *(node as *mut drm_i915_gem_context_create_ext_clone) };
+                d.clone_id = s.clone_id;
+                d.flags = s.flags;
+                d.rsvd = s.rsvd;
             }
             Ok(())
         })
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        extension_pointers(&self.extensions, pointers, |node, pointers| {
+            if snapshot::<i915_user_extension>(node).name == 0 {
+                let setparam = unsafe { &*(node as *const drm_i915_gem_context_create_ext_setparam) };
+                setparam.param.value_pointers(pointers);
+            }
+        });
+    }
+    fn free(&mut self) -> Result<(), Error> {
         let mut ext = self.extensions;
         while ext != 0 {
-            let base = unsafe { &mut *(ext as *mut i915_user_extension) };
+            check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+            let base = snapshot::<i915_user_extension>(ext);
             // Free other sub-items here.
             if base.name == 0 {
                 let param = unsafe { &mut *(ext as *mut drm_i915_gem_context_create_ext_setparam) };
@@ -963,134 +1497,321 @@ impl DeepCopy<drm_i915_gem_context_create_ext> for drm_i915_gem_context_create_e
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_wait {
     bo_handle: u32,
     flags: u32,
     timeout_ns: i64,
 }
+impl PointerFields for drm_i915_gem_wait {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_set_domain {
     handle: u32,
     read_domains: u32,
     write_domain: u32,
 }
+impl PointerFields for drm_i915_gem_set_domain {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_get_aperture {
     aper_size: u64,
     aper_available_size: u64,
 }
+impl PointerFields for drm_i915_gem_get_aperture {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_busy {
     handle: u32,
     busy: u32,
 }
+impl PointerFields for drm_i915_gem_busy {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_madvise {
     handle: u32,
     madv: u32,
     retained: u32,
 }
+impl PointerFields for drm_i915_gem_madvise {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_get_tiling {
     handle: u32,
     tiling_mode: u32,
     swizzle_mode: u32,
     phys_swizzle_mode: u32,
 }
+impl PointerFields for drm_i915_gem_get_tiling {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
-struct drm_i915_gem_sw_finish {
+#[derive(Debug)]
+struct drm_i915_gem_set_tiling {
     handle: u32,
+    tiling_mode: u32,
+    stride: u32,
+    swizzle_mode: u32,
 }
+impl PointerFields for drm_i915_gem_set_tiling {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
-struct drm_i915_get_pipe_from_crtc_id {
-    crtc_id: u32,
-    pipe: u32,
+#[derive(Debug)]
+struct drm_i915_gem_caching {
+    handle: u32,
+    caching: u32,
 }
+impl PointerFields for drm_i915_gem_caching {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
-struct prelim_drm_i915_gem_vm_region_ext {
-    base: i915_user_extension,
+#[derive(Debug)]
+struct drm_i915_gem_create {
+    size: u64,
+    handle: u32,
+    pad: u32,
+}
+impl PointerFields for drm_i915_gem_create {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_sw_finish {
+    handle: u32,
+}
+impl PointerFields for drm_i915_gem_sw_finish {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_get_pipe_from_crtc_id {
+    crtc_id: u32,
+    pipe: u32,
+}
+impl PointerFields for drm_i915_get_pipe_from_crtc_id {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_gem_vm_region_ext {
+    base: i915_user_extension,
     region: prelim_drm_i915_gem_memory_class_instance,
     pad: u32,
 }
+impl prelim_drm_i915_gem_vm_region_ext {
+    fn copy(&mut self, source: &prelim_drm_i915_gem_vm_region_ext) {
+        self.base.copy(&source.base);
+        self.region = source.region;
+        self.pad = source.pad;
+    }
+}
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_vm_control {
     extensions: u64,
     flags: u32,
     vm_id: u32,
 }
 impl drm_i915_gem_vm_control {
-    fn sizeof(name: u32) -> Result<usize, String> {
+    fn sizeof(name: u32) -> Result<usize, Error> {
         match name {
-            0 => Ok(mem::size_of::<prelim_drm_i915_gem_vm_region_ext>()), // PRELIM_I915_GEM_VM_CONTROL_EXT_REGION
-            _ => Err(format!("name is not supported")),
+            PRELIM_I915_GEM_VM_CONTROL_EXT_REGION => {
+                Ok(mem::size_of::<prelim_drm_i915_gem_vm_region_ext>())
+            }
+            _ => Err(Error::InvalidArgument(format!(
+                "vm_control: the extension name:0x{:x} is not supported",
+                name
+            ))),
         }
     }
     fn iterator(
         &mut self,
         source: &drm_i915_gem_vm_control,
-        f: fn(u64, &mut u64) -> Result<(), String>,
-    ) -> Result<(), String> {
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
         let ext_dst = &mut self.extensions;
         let ext_src = source.extensions;
         iterator::<_>(ext_src, ext_dst, f)
     }
 }
 impl DeepCopy<drm_i915_gem_vm_control> for drm_i915_gem_vm_control {
-    fn alloc(&mut self, source: &drm_i915_gem_vm_control) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_gem_vm_control) -> Result<(), Error> {
         self.extensions = 0;
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
-            let size = Self::sizeof(ext_src.name & PRELIM_I915_USER_EXT_MASK)?;
-            *dst = alloc(size)? as u64;
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.init();
+            let size = Self::sizeof(ext_src.name)?;
+            *dst = alloc_zeroed(size)? as u64;
+            unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
             Ok(())
         })
     }
-    fn copy(&mut self, source: &drm_i915_gem_vm_control, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_gem_vm_control, direction: Direction) -> Result<(), Error> {
         self.vm_id = source.vm_id;
         self.flags = source.flags;
+        if direction == Direction::u2t {
+            // The extensions are only read by the kernel, so never walk the host's copy of them.
+            return Ok(());
+        }
         // Deep copy
-        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), String> {
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
+            let ext_src = unsafe { &*(src as *const i915_user_extension) };
+            let size = Self::sizeof(ext_src.name)?;
+            let node = *dst;
+            check_untrusted_ptr("extension", node, size)?;
+            // sizeof() has rejected the other names.
+            if ext_src.name == PRELIM_I915_GEM_VM_CONTROL_EXT_REGION {
+                let s = unsafe { &*(src as *const prelim_drm_i915_gem_vm_region_ext) };
+                let d = unsafe { &mut *(node as *mut prelim_drm_i915_gem_vm_region_ext) };
+                d.copy(s);
+            }
+            Ok(())
+        })
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        extension_pointers(&self.extensions, pointers, |_, _| {});
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        let mut ext = self.extensions;
+        while ext != 0 {
+            check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+            let extension = snapshot::<i915_user_extension>(ext);
+            let size = Self::sizeof(extension.name)?;
+            let next = extension.next_extension;
+            free(ext as *mut u8, size)?;
+            ext = next;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_vm_bind_ext_sync_fence {
+    base: i915_user_extension,
+    addr: u64,
+    val: u64,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_vm_bind_ext_uuid {
+    base: i915_user_extension,
+    uuid_handle: u32,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_vm_bind_ext_set_pat {
+    base: i915_user_extension,
+    pat_index: u64,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_vm_bind_ext_user_fence {
+    base: i915_user_extension,
+    addr: u64,
+    val: u64,
+    rsvd: u64,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_gem_vm_bind {
+    vm_id: u32,
+    handle: u32,
+    start: u64,
+    offset: u64,
+    length: u64,
+    flags: u64,
+    extensions: u64,
+}
+impl prelim_drm_i915_gem_vm_bind {
+    fn sizeof(name: u32) -> Result<usize, Error> {
+        match name {
+            0 => Ok(mem::size_of::<prelim_drm_i915_vm_bind_ext_sync_fence>()), // PRELIM_I915_VM_BIND_EXT_SYNC_FENCE
+            1 => Ok(mem::size_of::<prelim_drm_i915_vm_bind_ext_uuid>()), // PRELIM_I915_VM_BIND_EXT_UUID
+            2 => Ok(mem::size_of::<prelim_drm_i915_vm_bind_ext_set_pat>()), // PRELIM_I915_VM_BIND_EXT_SET_PAT
+            3 => Ok(mem::size_of::<prelim_drm_i915_vm_bind_ext_user_fence>()), // PRELIM_I915_VM_BIND_EXT_USER_FENCE
+            _ => Err(Error::InvalidArgument(format!("the name:{:?} is illegal !!!", name))),
+        }
+    }
+    fn iterator(
+        &mut self,
+        source: &prelim_drm_i915_gem_vm_bind,
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let ext_src = source.extensions;
+        let ext_dst = &mut self.extensions;
+        iterator::<_>(ext_src, ext_dst, f)
+    }
+}
+impl DeepCopy<prelim_drm_i915_gem_vm_bind> for prelim_drm_i915_gem_vm_bind {
+    fn alloc(&mut self, source: &prelim_drm_i915_gem_vm_bind) -> Result<(), Error> {
+        self.extensions = 0;
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
             let ext_src = unsafe { &*(src as *const i915_user_extension) };
-            let next = unsafe { &*(*dst as *const i915_user_extension) }.next_extension;
             let size = Self::sizeof(ext_src.name & PRELIM_I915_USER_EXT_MASK)?;
-            unsafe {
-                ptr::copy(src as *const u8, *dst as *mut u8, size);
+            *dst = alloc_zeroed(size)? as u64;
+            unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
+            Ok(())
+        })
+    }
+    fn copy(&mut self, source: &prelim_drm_i915_gem_vm_bind, direction: Direction) -> Result<(), Error> {
+        self.vm_id = source.vm_id;
+        self.handle = source.handle;
+        self.start = source.start;
+        self.offset = source.offset;
+        self.length = source.length;
+        self.flags = source.flags;
+        if direction == Direction::u2t {
+            // The extensions are only read by the kernel, so never walk the host's copy of them.
+            return Ok(());
+        }
+        // Deep copy
+        self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
+            let ext_src = unsafe { &*(src as *const i915_user_extension) };
+            let name = ext_src.name & PRELIM_I915_USER_EXT_MASK;
+            let size = Self::sizeof(name)?;
+            // The kernel signals the fences by writing val to addr once the bind is done, which is
+            // impossible for an address inside enclave.
+            if name == 0 {
+                let fence = unsafe { &*(src as *const prelim_drm_i915_vm_bind_ext_sync_fence) };
+                check_input_ptr("addr", fence.addr, mem::size_of::<u64>())?;
+            } else if name == 3 {
+                let fence = unsafe { &*(src as *const prelim_drm_i915_vm_bind_ext_user_fence) };
+                check_input_ptr("addr", fence.addr, mem::size_of::<u64>())?;
             }
-            unsafe { &mut *(*dst as *mut i915_user_extension) }.next_extension = next;
-            /*let ext_src = unsafe { &*(src as *const i915_user_extension) };
-            let ext_dst = unsafe { &mut *(*dst as *mut i915_user_extension) };
-            ext_dst.copy(ext_src);
-            if ext_src.name & PRELIM_I915_USER_EXT_MASK == 0 {
-                let src = unsafe { &*(src as *const prelim_drm_i915_gem_vm_region_ext) };
-                let dst =
-                    unsafe { &mut *(*dst as *mut prelim_drm_i915_gem_vm_region_ext) };
-                dst.copy(src);
-            }*/
+            let node = *dst;
+            check_untrusted_ptr("extension", node, size)?;
+            let next = unsafe { &*(node as *const i915_user_extension) }.next_extension;
+            copy_buffer(Access::In, &Direction::t2u, src, node, size);
+            unsafe { &mut *(node as *mut i915_user_extension) }.next_extension = next;
             Ok(())
         })
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        // The addresses of the fences are the application's, they aren't translated.
+        extension_pointers(&self.extensions, pointers, |_, _| {});
+    }
+    fn free(&mut self) -> Result<(), Error> {
         let mut ext = self.extensions;
         while ext != 0 {
-            let extension = unsafe { &*(ext as *const i915_user_extension) };
+            check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+            let extension = snapshot::<i915_user_extension>(ext);
             let size = Self::sizeof(extension.name & PRELIM_I915_USER_EXT_MASK)?;
             let next = extension.next_extension;
             free(ext as *mut u8, size)?;
@@ -1102,6 +1823,87 @@ impl DeepCopy<drm_i915_gem_vm_control> for drm_i915_gem_vm_control {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_gem_wait_user_fence {
+    extensions: u64,
+    addr: u64,
+    ctx_id: u32,
+    op: u16,
+    flags: u16,
+    value: u64,
+    mask: u64,
+    timeout: i64,
+}
+impl PointerFields for prelim_drm_i915_gem_wait_user_fence {
+    fn input_pointers(&self) -> Vec<PointerField> {
+        // The fence is written by the GPU, so it must live in untrusted memory.
+        vec![("addr", self.addr, mem::size_of::<u64>())]
+    }
+}
+
+fn i915_gem_wait_user_fence_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    // The extensions MBZ, none is defined for PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE yet.
+    let extensions = unsafe { &*(arg as *const prelim_drm_i915_gem_wait_user_fence) }.extensions;
+    if extensions != 0 {
+        return Err(Error::InvalidArgument(format!(
+            "wait_user_fence: extensions:0x{:x} are not supported",
+            extensions
+        )));
+    }
+    exec::<prelim_drm_i915_gem_wait_user_fence>(fd, cmd, arg)
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct prelim_drm_i915_uuid_control {
+    uuid: [u8; 36],
+    uuid_class: u32,
+    flags: u32,
+    ptr: u64,
+    size: u64,
+    handle: u32,
+    extensions: u64,
+}
+impl DeepCopy<prelim_drm_i915_uuid_control> for prelim_drm_i915_uuid_control {
+    fn alloc(&mut self, source: &prelim_drm_i915_uuid_control) -> Result<(), Error> {
+        self.ptr = alloc(source.size as usize)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &prelim_drm_i915_uuid_control, direction: Direction) -> Result<(), Error> {
+        self.uuid = source.uuid;
+        self.uuid_class = source.uuid_class;
+        self.flags = source.flags;
+        self.handle = source.handle;
+        if direction == Direction::u2t {
+            // The payload and extensions are only read by the kernel.
+            return Ok(());
+        }
+        // Set before any error, free() needs it.
+        self.size = source.size;
+        // The extensions MBZ, none is defined for the uuid ioctls yet.
+        if source.extensions != 0 {
+            return Err(Error::InvalidArgument(format!(
+                "uuid_control: extensions:0x{:x} are not supported",
+                source.extensions
+            )));
+        }
+        self.extensions = 0;
+        // Deep copy
+        copy_buffer(Access::In, &direction, source.ptr, self.ptr, source.size as usize);
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.ptr));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(self.ptr as *mut u8, self.size as usize)
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_mmap_offset {
     handle: u32,
     pad: u32,
@@ -1109,9 +1911,175 @@ struct drm_i915_gem_mmap_offset {
     flags: u64,
     extensions: u64,
 }
+impl PointerFields for drm_i915_gem_mmap_offset {}
+
+// Note: The extensions are rejected rather than deep copied on purpose, upstream defines none for
+// DRM_IOCTL_I915_GEM_MMAP_OFFSET and the kernel fails a non-zero one with EINVAL anyway.
+fn i915_gem_mmap_offset_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let extensions = unsafe { &*(arg as *const drm_i915_gem_mmap_offset) }.extensions;
+    if extensions != 0 {
+        return Err(Error::InvalidArgument(format!(
+            "mmap_offset: extensions:0x{:x} are not supported",
+            extensions
+        )));
+    }
+    let ret = exec::<drm_i915_gem_mmap_offset>(fd, cmd, arg)?;
+    // Remember the fake offset, so that a later mmap() of it can be validated.
+    let mmap_offset = unsafe { &*(arg as *const drm_i915_gem_mmap_offset) };
+    if let Some(mmap_type) = MmapType::from_flags(mmap_offset.flags) {
+        insert_offset(fd, mmap_offset.offset, mmap_offset.handle, mmap_type);
+    }
+    Ok(ret)
+}
+
+fn i915_gem_mmap_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec::<drm_i915_gem_mmap>(fd, cmd, arg)?;
+    // The kernel has mapped the object already, track it to unmap it on GEM_CLOSE.
+    let mmap = unsafe { &*(arg as *const drm_i915_gem_mmap) };
+    insert_mapping(fd, mmap.handle, mmap.addr_ptr as usize, mmap.size as usize);
+    Ok(ret)
+}
+
+fn drm_gem_close_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let handle = unsafe { &*(arg as *const drm_gem_close_t) }.handle;
+    let ret = exec::<drm_gem_close_t>(fd, cmd, arg)?;
+    remove_object(fd, handle);
+    close_handle(fd, handle);
+    Ok(ret)
+}
+
+fn i915_gem_create_ext_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec2::<prelim_drm_i915_gem_create_ext>(fd, cmd, arg)?;
+    let create = unsafe { &*(arg as *const prelim_drm_i915_gem_create_ext) };
+    let mut object = GemObject {
+        handle: create.handle,
+        size: Some(create.size),
+        placements: Vec::new(),
+        protected: false,
+        origin: Origin::CreateExt,
+    };
+    // The extensions are the application's own, the kernel has accepted them.
+    let mut ext = create.extensions;
+    while ext != 0 {
+        let extension = snapshot::<i915_user_extension>(ext);
+        match extension.name & PRELIM_I915_USER_EXT_MASK {
+            1 => {
+                let setparam = snapshot::<prelim_drm_i915_gem_create_ext_setparam>(ext);
+                let offset = mem::size_of::<prelim_drm_i915_gem_memory_class_instance>();
+                for i in 0..setparam.param.size as usize {
+                    let region = snapshot::<prelim_drm_i915_gem_memory_class_instance>(
+                        setparam.param.data + (offset * i) as u64,
+                    );
+                    object.placements.push(MemoryRegion {
+                        memory_class: region.memory_class,
+                        memory_instance: region.memory_instance,
+                    });
+                }
+            }
+            3 => object.protected = true,
+            _ => {}
+        }
+        ext = extension.next_extension;
+    }
+    insert_object(fd, object);
+    Ok(ret)
+}
+
+// Reject an access outside the object, if its size is known.
+fn check_object_bounds(fd: i32, handle: u32, offset: u64, size: u64) -> Result<(), Error> {
+    if let Some(object_size) = lookup_object(fd, handle).and_then(|object| object.size) {
+        match offset.checked_add(size) {
+            Some(end) if end <= object_size => {}
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "handle:{:?}: [ 0x{:x}, +0x{:x} ] is out of the object's 0x{:x} bytes",
+                    handle, offset, size, object_size
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn i915_gem_pread_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let pread = unsafe { &*(arg as *const drm_i915_gem_pread) };
+    check_object_bounds(fd, pread.handle, pread.offset, pread.size)?;
+    exec2::<drm_i915_gem_pread>(fd, cmd, arg)
+}
+
+fn i915_gem_pwrite_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let pwrite = unsafe { &*(arg as *const drm_i915_gem_pwrite) };
+    check_object_bounds(fd, pwrite.handle, pwrite.offset, pwrite.size)?;
+    exec2::<drm_i915_gem_pwrite>(fd, cmd, arg)
+}
+
+fn i915_gem_create_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec::<drm_i915_gem_create>(fd, cmd, arg)?;
+    let create = unsafe { &*(arg as *const drm_i915_gem_create) };
+    insert_object(
+        fd,
+        GemObject {
+            handle: create.handle,
+            size: Some(create.size),
+            placements: Vec::new(),
+            protected: false,
+            origin: Origin::Create,
+        },
+    );
+    Ok(ret)
+}
+
+fn i915_gem_userptr_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec::<drm_i915_gem_userptr>(fd, cmd, arg)?;
+    let userptr = unsafe { &*(arg as *const drm_i915_gem_userptr) };
+    insert_object(
+        fd,
+        GemObject {
+            handle: userptr.handle,
+            size: Some(userptr.user_size),
+            placements: Vec::new(),
+            protected: false,
+            origin: Origin::Userptr(userptr.user_ptr),
+        },
+    );
+    Ok(ret)
+}
+
+fn drm_prime_fd_to_handle_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec::<drm_prime_handle>(fd, cmd, arg)?;
+    let prime = unsafe { &*(arg as *const drm_prime_handle) };
+    insert_object(
+        fd,
+        GemObject {
+            handle: prime.handle,
+            size: None,
+            placements: Vec::new(),
+            protected: false,
+            origin: Origin::Prime(prime.fd),
+        },
+    );
+    Ok(ret)
+}
+
+fn drm_gem_open_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, Error> {
+    let ret = exec::<drm_gem_open>(fd, cmd, arg)?;
+    let open = unsafe { &*(arg as *const drm_gem_open) };
+    insert_object(
+        fd,
+        GemObject {
+            handle: open.handle,
+            size: Some(open.size),
+            placements: Vec::new(),
+            protected: false,
+            origin: Origin::Flink(open.name),
+        },
+    );
+    Ok(ret)
+}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_reset_stats {
     ctx_id: u32,
     flags: u32,
@@ -1120,37 +2088,128 @@ struct drm_i915_reset_stats {
     batch_pending: u32,
     pad: u32,
 }
+impl PointerFields for drm_i915_reset_stats {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_reg_read {
     offset: u64,
     val: u64,
 }
+impl PointerFields for drm_i915_reg_read {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_prime_handle {
     handle: u32,
     flags: u32,
     fd: i32,
 }
+impl PointerFields for drm_prime_handle {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 pub struct drm_gem_close_t {
     handle: u32,
     pad: u32,
 }
+impl PointerFields for drm_gem_close_t {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_exec_fence {
     handle: u32,
     flags: u32,
 }
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_clip_rect {
+    x1: u16,
+    y1: u16,
+    x2: u16,
+    y2: u16,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_execbuffer_ext_timeline_fences {
+    base: i915_user_extension,
+    fence_count: u64,
+    handles_ptr: u64,
+    values_ptr: u64,
+}
+impl drm_i915_gem_execbuffer_ext_timeline_fences {
+    fn handles_size(fence_count: u64) -> Result<usize, Error> {
+        mem::size_of::<drm_i915_gem_exec_fence>()
+            .checked_mul(fence_count as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    fn values_size(fence_count: u64) -> Result<usize, Error> {
+        mem::size_of::<u64>()
+            .checked_mul(fence_count as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    fn alloc(&mut self, source: &drm_i915_gem_execbuffer_ext_timeline_fences) -> Result<(), Error> {
+        self.handles_ptr = alloc(Self::handles_size(source.fence_count)?)? as u64;
+        // The values are optional, binary syncobjs don't need any.
+        self.values_ptr = if source.values_ptr != 0 {
+            alloc(Self::values_size(source.fence_count)?)? as u64
+        } else {
+            crate::memory::PTR_NULL
+        };
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_i915_gem_execbuffer_ext_timeline_fences) -> Result<(), Error> {
+        let handles_ptr = self.handles_ptr;
+        let values_ptr = self.values_ptr;
+        let handles_size = Self::handles_size(source.fence_count)?;
+        let values_size = if values_ptr != 0 {
+            Self::values_size(source.fence_count)?
+        } else {
+            0
+        };
+        check_untrusted_ptr("handles_ptr", handles_ptr, handles_size)?;
+        check_untrusted_ptr("values_ptr", values_ptr, values_size)?;
+        self.base.copy(&source.base);
+        self.fence_count = source.fence_count;
+        self.handles_ptr = handles_ptr;
+        self.values_ptr = values_ptr;
+        copy_buffer(Access::In, &Direction::t2u, source.handles_ptr, handles_ptr, handles_size);
+        copy_buffer(Access::In, &Direction::t2u, source.values_ptr, values_ptr, values_size);
+        Ok(())
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        let fences =
+            snapshot::<drm_i915_gem_execbuffer_ext_timeline_fences>(self as *const _ as u64);
+        free(
+            fences.handles_ptr as *mut u8,
+            Self::handles_size(fences.fence_count)?,
+        )?;
+        free(
+            fences.values_ptr as *mut u8,
+            Self::values_size(fences.fence_count)?,
+        )
+    }
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_gem_relocation_entry {
+    target_handle: u32,
+    delta: u32,
+    offset: u64,
+    presumed_offset: u64,
+    read_domains: u32,
+    write_domain: u32,
+}
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_exec_object2 {
     handle: u32,
     relocation_count: u32,
@@ -1161,8 +2220,65 @@ struct drm_i915_gem_exec_object2 {
     rsvd1: u64,
     rsvd2: u64,
 }
+impl drm_i915_gem_exec_object2 {
+    fn relocs_size(relocation_count: u32) -> Result<usize, Error> {
+        mem::size_of::<drm_i915_gem_relocation_entry>()
+            .checked_mul(relocation_count as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    fn alloc(&mut self, source: &drm_i915_gem_exec_object2) -> Result<(), Error> {
+        self.relocs_ptr = alloc(Self::relocs_size(source.relocation_count)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_i915_gem_exec_object2, direction: &Direction) -> Result<(), Error> {
+        // Each side keeps its own relocation list, only the entries are copied.
+        let relocs_ptr = self.relocs_ptr;
+        let size = Self::relocs_size(source.relocation_count)?;
+        if direction == &Direction::t2u {
+            // The list is allocated by alloc(), but its address is read back from untrusted memory.
+            check_untrusted_ptr("relocs_ptr", relocs_ptr, size)?;
+            copy_buffer(Access::InOut, direction, source.relocs_ptr, relocs_ptr, size);
+        } else {
+            check_untrusted_size(
+                "relocation_count",
+                source.relocation_count as u64,
+                self.relocation_count as u64,
+            )?;
+            check_untrusted_ptr("relocs_ptr", source.relocs_ptr, size)?;
+            // The kernel only writes back the presumed offsets of the relocations.
+            let offset = mem::size_of::<drm_i915_gem_relocation_entry>();
+            for i in 0..source.relocation_count as usize {
+                let s = snapshot::<drm_i915_gem_relocation_entry>(
+                    source.relocs_ptr + (offset * i) as u64,
+                );
+                let d = unsafe {
+                    &mut *((relocs_ptr as *mut u8).add(offset * i)
+                        as *mut drm_i915_gem_relocation_entry)
+                };
+                d.presumed_offset = s.presumed_offset;
+            }
+        }
+        self.handle = source.handle;
+        self.relocation_count = source.relocation_count;
+        self.relocs_ptr = relocs_ptr;
+        self.alignment = source.alignment;
+        self.offset = source.offset;
+        self.flags = source.flags;
+        self.rsvd1 = source.rsvd1;
+        self.rsvd2 = source.rsvd2;
+        Ok(())
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        let object = snapshot::<drm_i915_gem_exec_object2>(self as *const _ as u64);
+        free(
+            object.relocs_ptr as *mut u8,
+            Self::relocs_size(object.relocation_count)?,
+        )
+    }
+}
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_execbuffer2 {
     buffers_ptr: u64,
     buffer_count: u32,
@@ -1176,27 +2292,124 @@ struct drm_i915_gem_execbuffer2 {
     rsvd1: u64,
     rsvd2: u64,
 }
+impl drm_i915_gem_execbuffer2 {
+    fn buffers_size(buffer_count: u32) -> Result<usize, Error> {
+        mem::size_of::<drm_i915_gem_exec_object2>()
+            .checked_mul(buffer_count as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    // The meaning of cliprects_ptr depends on the flags: an array of fences with
+    // I915_EXEC_FENCE_ARRAY, a chain of extensions with I915_EXEC_USE_EXTENSIONS (num_cliprects must
+    // be 0 then), and the legacy cliprects otherwise.
+    fn cliprects_size(flags: u64, num_cliprects: u32) -> Result<usize, Error> {
+        if flags & I915_EXEC_USE_EXTENSIONS != 0 {
+            return Ok(0);
+        }
+        if flags & I915_EXEC_FENCE_ARRAY != 0 {
+            mem::size_of::<drm_i915_gem_exec_fence>()
+        } else {
+            mem::size_of::<drm_clip_rect>()
+        }
+        .checked_mul(num_cliprects as usize)
+        .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    fn sizeof(name: u32) -> Result<usize, Error> {
+        match name {
+            0 => Ok(mem::size_of::<drm_i915_gem_execbuffer_ext_timeline_fences>()),
+            _ => Err(Error::InvalidArgument(format!("the name:{:?} is illegal !!!", name))),
+        }
+    }
+    fn iterator(
+        &mut self,
+        source: &drm_i915_gem_execbuffer2,
+        f: fn(u64, &mut u64) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let ext_src = source.cliprects_ptr;
+        let ext_dst = &mut self.cliprects_ptr;
+        iterator::<_>(ext_src, ext_dst, f)
+    }
+    fn objects_iterator(
+        &mut self,
+        source: &drm_i915_gem_execbuffer2,
+        direction: &Direction,
+        f: fn(&drm_i915_gem_exec_object2, &mut drm_i915_gem_exec_object2, direction: &Direction) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let offset = mem::size_of::<drm_i915_gem_exec_object2>();
+        for i in 0..source.buffer_count {
+            let s = snapshot::<drm_i915_gem_exec_object2>(
+                source.buffers_ptr
+                    + offset
+                        .checked_mul(i as usize)
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))? as u64,
+            );
+            let d = unsafe {
+                &mut *((self.buffers_ptr as *mut u8).add(
+                    offset
+                        .checked_mul(i as usize)
+                        .ok_or(Error::InvalidArgument(String::from("mul error")))?,
+                ) as *mut drm_i915_gem_exec_object2)
+            };
+            f(&s, d, direction)?;
+        }
+        Ok(())
+    }
+}
 impl DeepCopy<drm_i915_gem_execbuffer2> for drm_i915_gem_execbuffer2 {
-    fn alloc(&mut self, source: &drm_i915_gem_execbuffer2) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_gem_execbuffer2) -> Result<(), Error> {
+        self.flags = source.flags;
+        self.num_cliprects = source.num_cliprects;
+        self.cliprects_ptr = crate::memory::PTR_NULL;
         if source.buffer_count > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_object2>()
-                .checked_mul(source.buffer_count as usize)
-                .ok_or(format!("mul error"))?;
-            self.buffers_ptr = alloc(size)? as u64;
+            let size = Self::buffers_size(source.buffer_count)?;
+            self.buffers_ptr = alloc_zeroed(size)? as u64;
+            self.buffer_count = source.buffer_count;
+            self.objects_iterator(
+                source,
+                &Direction::none,
+                |src: &drm_i915_gem_exec_object2, dst: &mut drm_i915_gem_exec_object2, _: &Direction| {
+                    dst.alloc(src)
+                },
+            )?;
         } else {
             self.buffers_ptr = crate::memory::PTR_NULL;
         }
-        if source.num_cliprects > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_fence>()
-                .checked_mul(source.num_cliprects as usize)
-                .ok_or(format!("mul error"))?;
+        if source.flags & I915_EXEC_USE_EXTENSIONS != 0 {
+            self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
+                let ext_src = unsafe { &*(src as *const i915_user_extension) };
+                let size = Self::sizeof(ext_src.name)?;
+                *dst = alloc_zeroed(size)? as u64;
+                unsafe { &mut *(*dst as *mut i915_user_extension) }.init(ext_src.name);
+                let s = unsafe { &*(src as *const drm_i915_gem_execbuffer_ext_timeline_fences) };
+                let d = unsafe { &mut *(*dst as *mut drm_i915_gem_execbuffer_ext_timeline_fences) };
+                d.alloc(s)
+            })?;
+        } else if source.num_cliprects > 0 {
+            let size = Self::cliprects_size(source.flags, source.num_cliprects)?;
             self.cliprects_ptr = alloc(size)? as u64;
-        } else {
-            self.cliprects_ptr = crate::memory::PTR_NULL;
         }
         Ok(())
     }
-    fn copy(&mut self, source: &drm_i915_gem_execbuffer2, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_gem_execbuffer2, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            check_untrusted_size(
+                "buffer_count",
+                source.buffer_count as u64,
+                self.buffer_count as u64,
+            )?;
+            check_untrusted_size(
+                "num_cliprects",
+                source.num_cliprects as u64,
+                self.num_cliprects as u64,
+            )?;
+            check_untrusted_ptr(
+                "buffers_ptr",
+                source.buffers_ptr,
+                Self::buffers_size(source.buffer_count)?,
+            )?;
+            if source.flags != self.flags {
+                return Err(Error::UntrustedValue(String::from("flags: changed by the host")));
+            }
+        }
         self.batch_start_offset = source.batch_start_offset;
         self.batch_len = source.batch_len;
         self.dr1 = source.dr1;
@@ -1206,45 +2419,88 @@ impl DeepCopy<drm_i915_gem_execbuffer2> for drm_i915_gem_execbuffer2 {
         self.rsvd2 = source.rsvd2;
         // Deep copy
         self.buffer_count = source.buffer_count;
-        if source.buffer_count > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_object2>()
-                .checked_mul(source.buffer_count as usize)
-                .ok_or(format!("mul error"))?;
-            unsafe {
-                ptr::copy(
-                    source.buffers_ptr as *const u8,
-                    self.buffers_ptr as *mut u8,
-                    size,
-                );
-            }
-        }
+        self.objects_iterator(
+            source,
+            &direction,
+            |src: &drm_i915_gem_exec_object2, dst: &mut drm_i915_gem_exec_object2, direction: &Direction| {
+                dst.copy(src, direction)
+            },
+        )?;
         self.num_cliprects = source.num_cliprects;
+        if direction == Direction::u2t {
+            // The fences, cliprects and extensions are only read by the kernel.
+            return Ok(());
+        }
+        if source.flags & I915_EXEC_USE_EXTENSIONS != 0 {
+            return self.iterator(source, |src: u64, dst: &mut u64| -> Result<(), Error> {
+                let ext_src = unsafe { &*(src as *const i915_user_extension) };
+                let size = Self::sizeof(ext_src.name)?;
+                let node = *dst;
+                check_untrusted_ptr("extension", node, size)?;
+                unsafe { &mut *(node as *mut i915_user_extension) }.copy(ext_src);
+                let s = unsafe { &*(src as *const drm_i915_gem_execbuffer_ext_timeline_fences) };
+                let d = unsafe { &mut *(node as *mut drm_i915_gem_execbuffer_ext_timeline_fences) };
+                d.copy(s)
+            });
+        }
         if source.num_cliprects > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_fence>()
-                .checked_mul(source.num_cliprects as usize)
-                .ok_or(format!("mul error"))?;
-            unsafe {
-                ptr::copy(
-                    source.cliprects_ptr as *const u8,
-                    self.cliprects_ptr as *mut u8,
-                    size,
-                );
-            }
+            let size = Self::cliprects_size(source.flags, source.num_cliprects)?;
+            let cliprects_ptr = self.cliprects_ptr;
+            check_untrusted_ptr("cliprects_ptr", cliprects_ptr, size)?;
+            copy_buffer(Access::In, &direction, source.cliprects_ptr, cliprects_ptr, size);
         }
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        // The kernel writes back the offsets of the objects and of the relocations.
+        pointers.push(Access::InOut.pointer(&self.buffers_ptr));
+        let objects = elements::<drm_i915_gem_exec_object2>(&self.buffers_ptr, self.buffer_count as usize);
+        for object in objects {
+            pointers.push(Access::InOut.pointer(unsafe { ptr::addr_of!((*object).relocs_ptr) }));
+        }
+        if self.flags & I915_EXEC_USE_EXTENSIONS == 0 {
+            pointers.push(Access::In.pointer(&self.cliprects_ptr));
+            return;
+        }
+        extension_pointers(&self.cliprects_ptr, pointers, |node, pointers| {
+            let fences = node as *const drm_i915_gem_execbuffer_ext_timeline_fences;
+            pointers.push(Access::In.pointer(unsafe { ptr::addr_of!((*fences).handles_ptr) }));
+            pointers.push(Access::In.pointer(unsafe { ptr::addr_of!((*fences).values_ptr) }));
+        });
+    }
+    fn free(&mut self) -> Result<(), Error> {
         if self.buffer_count > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_object2>()
-                .checked_mul(self.buffer_count as usize)
-                .ok_or(format!("mul error"))?;
+            let size = Self::buffers_size(self.buffer_count)?;
+            for i in 0..self.buffer_count {
+                let object = unsafe {
+                    &mut *((self.buffers_ptr as *mut u8).add(
+                        mem::size_of::<drm_i915_gem_exec_object2>()
+                            .checked_mul(i as usize)
+                            .ok_or(Error::InvalidArgument(String::from("mul error")))?,
+                    ) as *mut drm_i915_gem_exec_object2)
+                };
+                object.free()?;
+            }
             free(self.buffers_ptr as *mut u8, size)?;
         }
-        if self.num_cliprects > 0 {
-            let size = mem::size_of::<drm_i915_gem_exec_fence>()
-                .checked_mul(self.num_cliprects as usize)
-                .ok_or(format!("mul error"))?;
-            free(self.cliprects_ptr as *mut u8, size)?;
+        if self.flags & I915_EXEC_USE_EXTENSIONS != 0 {
+            let mut ext = self.cliprects_ptr;
+            while ext != 0 {
+                check_untrusted_ptr("extension", ext, mem::size_of::<i915_user_extension>())?;
+                let extension = snapshot::<i915_user_extension>(ext);
+                let size = Self::sizeof(extension.name)?;
+                check_untrusted_ptr("extension", ext, size)?;
+                unsafe { &mut *(ext as *mut drm_i915_gem_execbuffer_ext_timeline_fences) }
+                    .free()?;
+                let next = extension.next_extension;
+                free(ext as *mut u8, size)?;
+                ext = next;
+            }
+        } else if self.num_cliprects > 0 {
+            free(
+                self.cliprects_ptr as *mut u8,
+                Self::cliprects_size(self.flags, self.num_cliprects)?,
+            )?;
         }
         Ok(())
     }
@@ -1252,30 +2508,32 @@ impl DeepCopy<drm_i915_gem_execbuffer2> for drm_i915_gem_execbuffer2 {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_userptr {
     user_ptr: u64,
     user_size: u64,
     flags: u32,
     handle: u32,
 }
-fn i915_gem_userptr_ioctl(fd: i32, cmd: &u32, arg: *const u8) -> Result<i32, String> {
-    let arg_t = unsafe { &*(arg as *mut u8 as *mut drm_i915_gem_userptr) };
-    if !sgx_trts::trts::rsgx_raw_is_outside_enclave(arg_t.user_ptr as *const u8, arg_t.user_size as usize) {
-        // Error: The user_ptr must outside enclave and should be set by applcation
-        return Err(format!("Can't map TRUSTED userptr: 0x{:x}", arg_t.user_ptr));
+impl PointerFields for drm_i915_gem_userptr {
+    fn input_pointers(&self) -> Vec<PointerField> {
+        // The user_ptr must be outside enclave and should be set by application
+        vec![("user_ptr", self.user_ptr, self.user_size as usize)]
     }
-    exec::<drm_i915_gem_userptr>(fd, cmd, arg)
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_context_destroy {
     ctx_id: u32,
     pad: u32,
 }
+impl PointerFields for drm_i915_gem_context_destroy {}
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_pread {
     handle: u32,
     pad: u32,
@@ -1284,34 +2542,40 @@ struct drm_i915_gem_pread {
     data_ptr: u64,
 }
 impl DeepCopy<drm_i915_gem_pread> for drm_i915_gem_pread {
-    fn alloc(&mut self, source: &drm_i915_gem_pread) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_gem_pread) -> Result<(), Error> {
         self.data_ptr = alloc(source.size as usize)? as u64;
         Ok(())
     }
-    fn copy(&mut self, source: &drm_i915_gem_pread, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_gem_pread, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            // Only the range validated before the ioctl is copied back, whatever the host reports.
+            check_untrusted_ptr("data_ptr", source.data_ptr, self.size as usize)?;
+            copy_buffer(
+                Access::Out,
+                &direction,
+                source.data_ptr,
+                self.data_ptr,
+                self.size as usize,
+            );
+            return Ok(());
+        }
         self.handle = source.handle;
         self.pad = source.pad;
         self.offset = source.offset;
         self.size = source.size;
-        // Deep copy
-        if source.size > 0 {
-            unsafe {
-                ptr::copy(
-                    source.data_ptr as *mut u8,
-                    self.data_ptr as *mut u8,
-                    source.size as usize,
-                );
-            }
-        }
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::Out.pointer(&self.data_ptr));
+    }
+    fn free(&mut self) -> Result<(), Error> {
         free(self.data_ptr as *mut u8, self.size as usize)
     }
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_pwrite {
     handle: u32,
     pad: u32,
@@ -1320,34 +2584,40 @@ struct drm_i915_gem_pwrite {
     data_ptr: u64,
 }
 impl DeepCopy<drm_i915_gem_pwrite> for drm_i915_gem_pwrite {
-    fn alloc(&mut self, source: &drm_i915_gem_pwrite) -> Result<(), String> {
+    fn alloc(&mut self, source: &drm_i915_gem_pwrite) -> Result<(), Error> {
         self.data_ptr = alloc(source.size as usize)? as u64;
         Ok(())
     }
-    fn copy(&mut self, source: &drm_i915_gem_pwrite, _: Direction) -> Result<(), String> {
+    fn copy(&mut self, source: &drm_i915_gem_pwrite, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            // Nothing is written back by the kernel.
+            return Ok(());
+        }
         self.handle = source.handle;
         self.pad = source.pad;
         self.offset = source.offset;
         self.size = source.size;
         // Deep copy
-        if source.size > 0 {
-            unsafe {
-                ptr::copy(
-                    source.data_ptr as *mut u8, 
-                    self.data_ptr as *mut u8, 
-                    source.size as usize
-                );
-            }
-        }
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.data_ptr,
+            self.data_ptr,
+            source.size as usize,
+        );
         Ok(())
     }
-    fn free(&mut self) -> Result<(), String> {
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.data_ptr));
+    }
+    fn free(&mut self) -> Result<(), Error> {
         free(self.data_ptr as *mut u8, self.size as usize)
     }
 }
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
+#[derive(Debug)]
 struct drm_i915_gem_mmap {
     handle: u32,
     pad: u32,
@@ -1356,19 +2626,742 @@ struct drm_i915_gem_mmap {
     addr_ptr: u64,
     flags: u64,
 }
+impl PointerFields for drm_i915_gem_mmap {
+    fn output_pointers(&self) -> Vec<PointerField> {
+        vec![("addr_ptr", self.addr_ptr, self.size as usize)]
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_perf_open_param {
+    flags: u32,
+    num_properties: u32,
+    properties_ptr: u64,
+}
+impl drm_i915_perf_open_param {
+    // Each property is a pair of u64: (id, value).
+    fn properties_size(num_properties: u32) -> Result<usize, Error> {
+        (mem::size_of::<u64>() * 2)
+            .checked_mul(num_properties as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+    // The properties of the application, as (id, value).
+    fn properties(source: &drm_i915_perf_open_param) -> impl Iterator<Item = (u64, u64)> {
+        let ptr = source.properties_ptr as *const [u64; 2];
+        (0..source.num_properties as usize).map(move |i| {
+            let [id, value] = unsafe { ptr::read_unaligned(ptr.add(i)) };
+            (id, value)
+        })
+    }
+    // The value of DRM_I915_PERF_PROP_GLOBAL_SSEU points to a drm_i915_gem_context_param_sseu. They
+    // are copied right after the properties, in the same block.
+    fn block_size(source: &drm_i915_perf_open_param) -> Result<usize, Error> {
+        let properties = Self::properties_size(source.num_properties)?;
+        let sseus = Self::properties(source)
+            .filter(|(id, _)| *id == DRM_I915_PERF_PROP_GLOBAL_SSEU)
+            .count();
+        mem::size_of::<drm_i915_gem_context_param_sseu>()
+            .checked_mul(sseus)
+            .and_then(|size| size.checked_add(properties))
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+}
+impl DeepCopy<drm_i915_perf_open_param> for drm_i915_perf_open_param {
+    fn alloc(&mut self, source: &drm_i915_perf_open_param) -> Result<(), Error> {
+        self.properties_ptr = alloc(Self::block_size(source)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_i915_perf_open_param, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            // The properties are only read by the kernel, the stream fd is the return value.
+            return Ok(());
+        }
+        self.flags = source.flags;
+        self.num_properties = source.num_properties;
+        // Deep copy
+        let size = Self::properties_size(source.num_properties)?;
+        copy_buffer(Access::In, &direction, source.properties_ptr, self.properties_ptr, size);
+        let properties = self.properties_ptr as *mut [u64; 2];
+        let mut sseu = self.properties_ptr + size as u64;
+        for (i, (id, value)) in Self::properties(source).enumerate() {
+            if id != DRM_I915_PERF_PROP_GLOBAL_SSEU {
+                continue;
+            }
+            let len = mem::size_of::<drm_i915_gem_context_param_sseu>();
+            copy_buffer(Access::In, &direction, value, sseu, len);
+            unsafe {
+                ptr::write_unaligned((properties.add(i) as *mut u64).add(1), sseu);
+            }
+            sseu += len as u64;
+        }
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.properties_ptr));
+        // The values of DRM_I915_PERF_PROP_GLOBAL_SSEU point into the same block.
+        for property in elements::<[u64; 2]>(&self.properties_ptr, self.num_properties as usize) {
+            let id = property as *const u64;
+            if unsafe { ptr::read_unaligned(id) } == DRM_I915_PERF_PROP_GLOBAL_SSEU {
+                pointers.push(Access::In.pointer(id.wrapping_add(1)));
+            }
+        }
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        // The sseu go with the properties.
+        free(
+            self.properties_ptr as *mut u8,
+            Self::properties_size(self.num_properties)?,
+        )
+    }
+}
 
-fn drm_default_ioctl(_fd: i32, cmd: &u32, _arg: *const u8) -> Result<i32, String> {
-    info!("unsupported ioctl:{:?} !!!", cmd);
-    Err(format!("unsupported ioctl: {:?}", cmd))
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_i915_perf_oa_config {
+    uuid: [u8; 36],
+    n_mux_regs: u32,
+    n_boolean_regs: u32,
+    n_flex_regs: u32,
+    mux_regs_ptr: u64,
+    boolean_regs_ptr: u64,
+    flex_regs_ptr: u64,
+}
+impl drm_i915_perf_oa_config {
+    // Each register is a pair of u32: (address, value).
+    fn regs_size(n_regs: u32) -> Result<usize, Error> {
+        (mem::size_of::<u32>() * 2)
+            .checked_mul(n_regs as usize)
+            .ok_or(Error::InvalidArgument(String::from("mul error")))
+    }
+}
+impl DeepCopy<drm_i915_perf_oa_config> for drm_i915_perf_oa_config {
+    fn alloc(&mut self, source: &drm_i915_perf_oa_config) -> Result<(), Error> {
+        self.mux_regs_ptr = alloc(Self::regs_size(source.n_mux_regs)?)? as u64;
+        self.boolean_regs_ptr = alloc(Self::regs_size(source.n_boolean_regs)?)? as u64;
+        self.flex_regs_ptr = alloc(Self::regs_size(source.n_flex_regs)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_i915_perf_oa_config, direction: Direction) -> Result<(), Error> {
+        if direction == Direction::u2t {
+            // The registers are only read by the kernel, the config id is the return value.
+            return Ok(());
+        }
+        self.uuid = source.uuid;
+        self.n_mux_regs = source.n_mux_regs;
+        self.n_boolean_regs = source.n_boolean_regs;
+        self.n_flex_regs = source.n_flex_regs;
+        // Deep copy
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.mux_regs_ptr,
+            self.mux_regs_ptr,
+            Self::regs_size(source.n_mux_regs)?,
+        );
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.boolean_regs_ptr,
+            self.boolean_regs_ptr,
+            Self::regs_size(source.n_boolean_regs)?,
+        );
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.flex_regs_ptr,
+            self.flex_regs_ptr,
+            Self::regs_size(source.n_flex_regs)?,
+        );
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.mux_regs_ptr));
+        pointers.push(Access::In.pointer(&self.boolean_regs_ptr));
+        pointers.push(Access::In.pointer(&self.flex_regs_ptr));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(self.mux_regs_ptr as *mut u8, Self::regs_size(self.n_mux_regs)?)?;
+        free(
+            self.boolean_regs_ptr as *mut u8,
+            Self::regs_size(self.n_boolean_regs)?,
+        )?;
+        free(self.flex_regs_ptr as *mut u8, Self::regs_size(self.n_flex_regs)?)
+    }
+}
+
+// The argument of DRM_IOCTL_I915_PERF_REMOVE_CONFIG is the config id.
+impl PointerFields for u64 {}
+
+fn drm_default_ioctl(_fd: i32, cmd: &u32, _arg: *const u8) -> Result<i32, Error> {
+    info!("unsupported ioctl: {} !!!", describe_ioc(*cmd));
+    Err(Error::UnsupportedCommand(cmd.to_owned()))
     //Ok(ioctl(_fd, cmd, _arg))
 }
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_create {
+    handle: u32,
+    flags: u32,
+}
+impl PointerFields for drm_syncobj_create {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_destroy {
+    handle: u32,
+    pad: u32,
+}
+impl PointerFields for drm_syncobj_destroy {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_handle {
+    handle: u32,
+    flags: u32,
+    fd: i32,
+    pad: u32,
+}
+impl PointerFields for drm_syncobj_handle {}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_transfer {
+    src_handle: u32,
+    dst_handle: u32,
+    src_point: u64,
+    dst_point: u64,
+    flags: u32,
+    pad: u32,
+}
+impl PointerFields for drm_syncobj_transfer {}
+
+// The size of an array of syncobj handles (u32) or points (u64).
+fn syncobj_array_size<T>(count_handles: u32) -> Result<usize, Error> {
+    mem::size_of::<T>()
+        .checked_mul(count_handles as usize)
+        .ok_or(Error::InvalidArgument(String::from("mul error")))
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_wait {
+    handles: u64,
+    timeout_nsec: i64,
+    count_handles: u32,
+    flags: u32,
+    first_signaled: u32,
+    pad: u32,
+}
+impl DeepCopy<drm_syncobj_wait> for drm_syncobj_wait {
+    fn alloc(&mut self, source: &drm_syncobj_wait) -> Result<(), Error> {
+        self.handles = alloc(syncobj_array_size::<u32>(source.count_handles)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_syncobj_wait, direction: Direction) -> Result<(), Error> {
+        self.timeout_nsec = source.timeout_nsec;
+        self.flags = source.flags;
+        self.first_signaled = source.first_signaled;
+        self.pad = source.pad;
+        if direction == Direction::u2t {
+            // The handles are only read by the kernel.
+            return check_untrusted_size(
+                "count_handles",
+                source.count_handles as u64,
+                self.count_handles as u64,
+            );
+        }
+        self.count_handles = source.count_handles;
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.handles,
+            self.handles,
+            syncobj_array_size::<u32>(source.count_handles)?,
+        );
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.handles));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(
+            self.handles as *mut u8,
+            syncobj_array_size::<u32>(self.count_handles)?,
+        )
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_timeline_wait {
+    handles: u64,
+    points: u64,
+    timeout_nsec: i64,
+    count_handles: u32,
+    flags: u32,
+    first_signaled: u32,
+    pad: u32,
+}
+impl DeepCopy<drm_syncobj_timeline_wait> for drm_syncobj_timeline_wait {
+    fn alloc(&mut self, source: &drm_syncobj_timeline_wait) -> Result<(), Error> {
+        self.handles = alloc(syncobj_array_size::<u32>(source.count_handles)?)? as u64;
+        self.points = alloc(syncobj_array_size::<u64>(source.count_handles)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_syncobj_timeline_wait, direction: Direction) -> Result<(), Error> {
+        self.timeout_nsec = source.timeout_nsec;
+        self.flags = source.flags;
+        self.first_signaled = source.first_signaled;
+        self.pad = source.pad;
+        if direction == Direction::u2t {
+            // The handles and points are only read by the kernel.
+            return check_untrusted_size(
+                "count_handles",
+                source.count_handles as u64,
+                self.count_handles as u64,
+            );
+        }
+        self.count_handles = source.count_handles;
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.handles,
+            self.handles,
+            syncobj_array_size::<u32>(source.count_handles)?,
+        );
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.points,
+            self.points,
+            syncobj_array_size::<u64>(source.count_handles)?,
+        );
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.handles));
+        pointers.push(Access::In.pointer(&self.points));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(
+            self.handles as *mut u8,
+            syncobj_array_size::<u32>(self.count_handles)?,
+        )?;
+        free(
+            self.points as *mut u8,
+            syncobj_array_size::<u64>(self.count_handles)?,
+        )
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_array {
+    handles: u64,
+    count_handles: u32,
+    pad: u32,
+}
+impl DeepCopy<drm_syncobj_array> for drm_syncobj_array {
+    fn alloc(&mut self, source: &drm_syncobj_array) -> Result<(), Error> {
+        self.handles = alloc(syncobj_array_size::<u32>(source.count_handles)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_syncobj_array, direction: Direction) -> Result<(), Error> {
+        self.pad = source.pad;
+        if direction == Direction::u2t {
+            // The handles are only read by the kernel.
+            return check_untrusted_size(
+                "count_handles",
+                source.count_handles as u64,
+                self.count_handles as u64,
+            );
+        }
+        self.count_handles = source.count_handles;
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.handles,
+            self.handles,
+            syncobj_array_size::<u32>(source.count_handles)?,
+        );
+        Ok(())
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        pointers.push(Access::In.pointer(&self.handles));
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(
+            self.handles as *mut u8,
+            syncobj_array_size::<u32>(self.count_handles)?,
+        )
+    }
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_timeline_array {
+    handles: u64,
+    points: u64,
+    count_handles: u32,
+    flags: u32,
+}
+impl drm_syncobj_timeline_array {
+    // The handles are always read by the kernel, the points depend on the command.
+    fn copy_points(
+        &mut self,
+        source: &drm_syncobj_timeline_array,
+        direction: Direction,
+        points: Access,
+    ) -> Result<(), Error> {
+        let size = syncobj_array_size::<u64>(source.count_handles)?;
+        self.flags = source.flags;
+        if direction == Direction::u2t {
+            check_untrusted_size(
+                "count_handles",
+                source.count_handles as u64,
+                self.count_handles as u64,
+            )?;
+            if points.copies(&direction) {
+                check_untrusted_ptr("points", source.points, size)?;
+            }
+            copy_buffer(points, &direction, source.points, self.points, size);
+            return Ok(());
+        }
+        self.count_handles = source.count_handles;
+        copy_buffer(
+            Access::In,
+            &direction,
+            source.handles,
+            self.handles,
+            syncobj_array_size::<u32>(source.count_handles)?,
+        );
+        copy_buffer(points, &direction, source.points, self.points, size);
+        Ok(())
+    }
+    fn points_pointers(&self, pointers: &mut Vec<Pointer>, points: Access) {
+        pointers.push(Access::In.pointer(&self.handles));
+        pointers.push(points.pointer(&self.points));
+    }
+}
+// DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL only reads the points.
+impl DeepCopy<drm_syncobj_timeline_array> for drm_syncobj_timeline_array {
+    fn alloc(&mut self, source: &drm_syncobj_timeline_array) -> Result<(), Error> {
+        self.handles = alloc(syncobj_array_size::<u32>(source.count_handles)?)? as u64;
+        self.points = alloc(syncobj_array_size::<u64>(source.count_handles)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_syncobj_timeline_array, direction: Direction) -> Result<(), Error> {
+        self.copy_points(source, direction, Access::In)
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        self.points_pointers(pointers, Access::In);
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        free(
+            self.handles as *mut u8,
+            syncobj_array_size::<u32>(self.count_handles)?,
+        )?;
+        free(
+            self.points as *mut u8,
+            syncobj_array_size::<u64>(self.count_handles)?,
+        )
+    }
+}
+
+// The argument of DRM_IOCTL_SYNCOBJ_QUERY, which only writes the points.
+#[repr(transparent)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+struct drm_syncobj_timeline_query(drm_syncobj_timeline_array);
+impl DeepCopy<drm_syncobj_timeline_query> for drm_syncobj_timeline_query {
+    fn alloc(&mut self, source: &drm_syncobj_timeline_query) -> Result<(), Error> {
+        let count = source.0.count_handles;
+        self.0.handles = alloc(syncobj_array_size::<u32>(count)?)? as u64;
+        self.0.points = alloc_zeroed(syncobj_array_size::<u64>(count)?)? as u64;
+        Ok(())
+    }
+    fn copy(&mut self, source: &drm_syncobj_timeline_query, direction: Direction) -> Result<(), Error> {
+        self.0.copy_points(&source.0, direction, Access::Out)
+    }
+    fn pointers(&self, pointers: &mut Vec<Pointer>) {
+        self.0.points_pointers(pointers, Access::Out);
+    }
+    fn free(&mut self) -> Result<(), Error> {
+        self.0.free()
+    }
+}
+
+// The name, family and argument type of the commands, for the trace of pxp_ioctl(). The commands
+// without a type take a value rather than a pointer.
+macro_rules! ioctl_table {
+    ($($cmd:ident: $family:ident $(, $ty:ty)?;)*) => {
+        fn ioctl_info(cmd: u32) -> Option<(&'static str, IoctlFamily)> {
+            match cmd {
+                $($cmd => Some((stringify!($cmd), IoctlFamily::$family)),)*
+                _ => None,
+            }
+        }
+
+        fn describe_arg(cmd: u32, arg: *const u8) -> String {
+            match cmd {
+                $($cmd => describe_as!(arg $(, $ty)?),)*
+                _ => format!("0x{:x}", arg as usize),
+            }
+        }
+    };
+}
+
+macro_rules! describe_as {
+    ($arg:ident) => {
+        format!("0x{:x}", $arg as usize)
+    };
+    ($arg:ident, $ty:ty) => {
+        debug_at::<$ty>($arg as u64)
+    };
+}
+
+ioctl_table! {
+    DRM_IOCTL_VERSION: Drm, drm_version;
+    DRM_IOCTL_GET_UNIQUE: Drm, drm_unique;
+    DRM_IOCTL_GET_MAGIC: Drm, drm_auth;
+    DRM_IOCTL_AUTH_MAGIC: Drm, drm_auth;
+    DRM_IOCTL_GET_CAP: Drm, drm_get_cap;
+    DRM_IOCTL_SET_CLIENT_CAP: Drm, drm_set_client_cap;
+    DRM_IOCTL_SET_VERSION: Drm, drm_set_version;
+    DRM_IOCTL_I915_GETPARAM: Drm, drm_i915_getparam;
+    DRM_IOCTL_I915_GET_PIPE_FROM_CRTC_ID: Drm, drm_i915_get_pipe_from_crtc_id;
+    DRM_IOCTL_I915_REG_READ: Drm, drm_i915_reg_read;
+    DRM_IOCTL_GEM_CLOSE: Gem, drm_gem_close_t;
+    DRM_IOCTL_GEM_FLINK: Gem, drm_gem_flink;
+    DRM_IOCTL_GEM_OPEN: Gem, drm_gem_open;
+    DRM_IOCTL_I915_GEM_CREATE: Gem, drm_i915_gem_create;
+    DRM_IOCTL_I915_GEM_CREATE_EXT: Gem, prelim_drm_i915_gem_create_ext;
+    DRM_IOCTL_I915_GEM_MMAP_OFFSET: Gem, drm_i915_gem_mmap_offset;
+    DRM_IOCTL_I915_GEM_MMAP: Gem, drm_i915_gem_mmap;
+    DRM_IOCTL_I915_GEM_GET_APERTURE: Gem, drm_i915_gem_get_aperture;
+    DRM_IOCTL_I915_GEM_SET_DOMAIN: Gem, drm_i915_gem_set_domain;
+    DRM_IOCTL_I915_GEM_USERPTR: Gem, drm_i915_gem_userptr;
+    DRM_IOCTL_I915_GEM_GET_TILING: Gem, drm_i915_gem_get_tiling;
+    DRM_IOCTL_I915_GEM_SET_TILING: Gem, drm_i915_gem_set_tiling;
+    DRM_IOCTL_I915_GEM_SET_CACHING: Gem, drm_i915_gem_caching;
+    DRM_IOCTL_I915_GEM_GET_CACHING: Gem, drm_i915_gem_caching;
+    DRM_IOCTL_I915_GEM_THROTTLE: Gem;
+    DRM_IOCTL_I915_GEM_WAIT: Gem, drm_i915_gem_wait;
+    DRM_IOCTL_I915_GEM_BUSY: Gem, drm_i915_gem_busy;
+    DRM_IOCTL_PRIME_HANDLE_TO_FD: Gem, drm_prime_handle;
+    DRM_IOCTL_PRIME_FD_TO_HANDLE: Gem, drm_prime_handle;
+    DRM_IOCTL_I915_GEM_SW_FINISH: Gem, drm_i915_gem_sw_finish;
+    DRM_IOCTL_I915_GEM_MADVISE: Gem, drm_i915_gem_madvise;
+    DRM_IOCTL_I915_GEM_PREAD: Gem, drm_i915_gem_pread;
+    DRM_IOCTL_I915_GEM_PWRITE: Gem, drm_i915_gem_pwrite;
+    DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT: Context, drm_i915_gem_context_create_ext;
+    DRM_IOCTL_I915_GEM_CONTEXT_DESTROY: Context, drm_i915_gem_context_destroy;
+    DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM: Context, drm_i915_gem_context_param;
+    DRM_IOCTL_I915_GEM_CONTEXT_SETPARAM: Context, drm_i915_gem_context_param;
+    DRM_IOCTL_I915_GET_RESET_STATS: Context, drm_i915_reset_stats;
+    DRM_IOCTL_I915_GEM_VM_CREATE: Vm, drm_i915_gem_vm_control;
+    DRM_IOCTL_I915_GEM_VM_DESTROY: Vm, drm_i915_gem_vm_control;
+    PRELIM_DRM_IOCTL_I915_GEM_VM_BIND: Vm, prelim_drm_i915_gem_vm_bind;
+    PRELIM_DRM_IOCTL_I915_GEM_VM_UNBIND: Vm, prelim_drm_i915_gem_vm_bind;
+    PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE: Vm, prelim_drm_i915_gem_wait_user_fence;
+    PRELIM_DRM_IOCTL_I915_UUID_REGISTER: Vm, prelim_drm_i915_uuid_control;
+    PRELIM_DRM_IOCTL_I915_UUID_UNREGISTER: Vm, prelim_drm_i915_uuid_control;
+    DRM_IOCTL_I915_GEM_EXECBUFFER2: Exec, drm_i915_gem_execbuffer2;
+    DRM_IOCTL_I915_GEM_EXECBUFFER2_WR: Exec, drm_i915_gem_execbuffer2;
+    DRM_IOCTL_I915_QUERY: Query, drm_i915_query;
+    PRELIM_DRM_IOCTL_I915_PXP_OPS: Pxp, prelim_drm_i915_pxp_ops;
+    DRM_IOCTL_I915_PERF_OPEN: Perf, drm_i915_perf_open_param;
+    DRM_IOCTL_I915_PERF_ADD_CONFIG: Perf, drm_i915_perf_oa_config;
+    DRM_IOCTL_I915_PERF_REMOVE_CONFIG: Perf, u64;
+    I915_PERF_IOCTL_ENABLE: Perf;
+    I915_PERF_IOCTL_DISABLE: Perf;
+    I915_PERF_IOCTL_CONFIG: Perf;
+    DRM_IOCTL_SYNCOBJ_CREATE: Syncobj, drm_syncobj_create;
+    DRM_IOCTL_SYNCOBJ_DESTROY: Syncobj, drm_syncobj_destroy;
+    DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD: Syncobj, drm_syncobj_handle;
+    DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE: Syncobj, drm_syncobj_handle;
+    DRM_IOCTL_SYNCOBJ_WAIT: Syncobj, drm_syncobj_wait;
+    DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT: Syncobj, drm_syncobj_timeline_wait;
+    DRM_IOCTL_SYNCOBJ_RESET: Syncobj, drm_syncobj_array;
+    DRM_IOCTL_SYNCOBJ_SIGNAL: Syncobj, drm_syncobj_array;
+    DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL: Syncobj, drm_syncobj_timeline_array;
+    DRM_IOCTL_SYNCOBJ_QUERY: Syncobj, drm_syncobj_timeline_query;
+    DRM_IOCTL_SYNCOBJ_TRANSFER: Syncobj, drm_syncobj_transfer;
+}
+
+// Bounds the extension chains printed, which may loop.
+const MAX_TRACED_EXTENSIONS: usize = 16;
+
+fn debug_at<T: fmt::Debug>(addr: u64) -> String {
+    if addr == crate::memory::PTR_NULL {
+        return String::from("NULL");
+    }
+    format!("{:?}", unsafe { &*(addr as *const T) })
+}
+
+// The extension chain starting at addr, each node printed by ext from its name.
+fn describe_extensions(mut addr: u64, ext: fn(u32, u64) -> Option<String>) -> String {
+    let mut nodes = Vec::new();
+    while addr != crate::memory::PTR_NULL {
+        if nodes.len() == MAX_TRACED_EXTENSIONS {
+            nodes.push(String::from("..."));
+            break;
+        }
+        let node = unsafe { &*(addr as *const i915_user_extension) };
+        nodes.push(ext(node.name, addr).unwrap_or(format!("{{ name: 0x{:x} }}", node.name)));
+        addr = node.next_extension;
+    }
+    format!("[{}]", nodes.join(", "))
+}
+
+// What the Debug of the argument only gives as pointers: the extension chains and the parameters of
+// the PXP actions.
+fn describe_extra(cmd: u32, arg: *const u8) -> Option<String> {
+    let extensions = match cmd {
+        DRM_IOCTL_I915_GEM_CREATE_EXT => {
+            let t = unsafe { &*(arg as *const prelim_drm_i915_gem_create_ext) };
+            describe_extensions(t.extensions, |name, ext| {
+                match name & PRELIM_I915_USER_EXT_MASK {
+                    1 => Some(debug_at::<prelim_drm_i915_gem_create_ext_setparam>(ext)),
+                    2 => Some(debug_at::<prelim_drm_i915_gem_create_ext_vm_private>(ext)),
+                    3 => Some(debug_at::<prelim_drm_i915_gem_create_ext_protected_content>(ext)),
+                    _ => None,
+                }
+            })
+        }
+        DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT => {
+            let t = unsafe { &*(arg as *const drm_i915_gem_context_create_ext) };
+            describe_extensions(t.extensions, |name, ext| match name {
+                0 => Some(debug_at::<drm_i915_gem_context_create_ext_setparam>(ext)),
+                1 => Some(debug_at::<drm_i915_gem_context_create_ext_clone>(ext)),
+                _ => None,
+            })
+        }
+        DRM_IOCTL_I915_GEM_VM_CREATE => {
+            let t = unsafe { &*(arg as *const drm_i915_gem_vm_control) };
+            describe_extensions(t.extensions, |name, ext| match name {
+                PRELIM_I915_GEM_VM_CONTROL_EXT_REGION => {
+                    Some(debug_at::<prelim_drm_i915_gem_vm_region_ext>(ext))
+                }
+                _ => None,
+            })
+        }
+        DRM_IOCTL_I915_GEM_EXECBUFFER2 | DRM_IOCTL_I915_GEM_EXECBUFFER2_WR => {
+            let t = unsafe { &*(arg as *const drm_i915_gem_execbuffer2) };
+            if t.flags & I915_EXEC_USE_EXTENSIONS == 0 {
+                return None;
+            }
+            describe_extensions(t.cliprects_ptr, |name, ext| match name {
+                0 => Some(debug_at::<drm_i915_gem_execbuffer_ext_timeline_fences>(ext)),
+                _ => None,
+            })
+        }
+        PRELIM_DRM_IOCTL_I915_GEM_VM_BIND | PRELIM_DRM_IOCTL_I915_GEM_VM_UNBIND => {
+            let t = unsafe { &*(arg as *const prelim_drm_i915_gem_vm_bind) };
+            describe_extensions(t.extensions, |name, ext| {
+                match name & PRELIM_I915_USER_EXT_MASK {
+                    0 => Some(debug_at::<prelim_drm_i915_vm_bind_ext_sync_fence>(ext)),
+                    1 => Some(debug_at::<prelim_drm_i915_vm_bind_ext_uuid>(ext)),
+                    2 => Some(debug_at::<prelim_drm_i915_vm_bind_ext_set_pat>(ext)),
+                    3 => Some(debug_at::<prelim_drm_i915_vm_bind_ext_user_fence>(ext)),
+                    _ => None,
+                }
+            })
+        }
+        DRM_IOCTL_I915_GEM_MMAP_OFFSET => {
+            let t = unsafe { &*(arg as *const drm_i915_gem_mmap_offset) };
+            describe_extensions(t.extensions, |_, _| None)
+        }
+        PRELIM_DRM_IOCTL_I915_PXP_OPS => {
+            let ops = unsafe { &*(arg as *const prelim_drm_i915_pxp_ops) };
+            let params = { ops.params };
+            return match ops.action {
+                0 => Some(format!(
+                    "SET_SESSION_STATUS {}",
+                    debug_at::<prelim_drm_i915_pxp_set_session_status_params>(params)
+                )),
+                1 => Some(format!(
+                    "TEE_IO_MESSAGE {}",
+                    debug_at::<prelim_drm_i915_pxp_tee_io_message_params>(params)
+                )),
+                2 => Some(format!(
+                    "QUERY_PXP_TAG {}",
+                    debug_at::<prelim_drm_i915_pxp_query_tag>(params)
+                )),
+                _ => None,
+            };
+        }
+        _ => return None,
+    };
+    Some(format!("extensions: {}", extensions))
+}
+
+// An unknown command decoded like the _IOC() macro of the kernel.
+fn describe_ioc(cmd: u32) -> String {
+    let dir = match cmd >> 30 {
+        0 => "_IOC_NONE",
+        1 => "_IOC_WRITE",
+        2 => "_IOC_READ",
+        _ => "_IOC_READ|_IOC_WRITE",
+    };
+    format!(
+        "_IOC({}, 0x{:x}, 0x{:x}, 0x{:x})",
+        dir,
+        (cmd >> 8) & 0xff,
+        cmd & 0xff,
+        (cmd >> 16) & 0x3fff
+    )
+}
+
+// Log a call of pxp_ioctl() the way strace prints a syscall. The argument is read after the call,
+// once it was accepted by the marshalling, which reads it the same way.
+fn trace_ioctl(fd: i32, cmd: u32, arg: *const u8, ret: &Result<i32, Error>, start: Option<u64>) {
+    let call = match ioctl_info(cmd) {
+        Some((name, _)) => {
+            let mut args = describe_arg(cmd, arg);
+            if !arg.is_null() {
+                if let Some(extra) = describe_extra(cmd, arg) {
+                    args = format!("{}, {}", args, extra);
+                }
+            }
+            format!("{}({})", name, args)
+        }
+        None => format!("{}(0x{:x})", describe_ioc(cmd), arg as usize),
+    };
+    let result = match ret {
+        Ok(ret) => format!("{}", ret),
+        Err(e) => format!("-1 errno {} ({})", e.errno(), e),
+    };
+    let latency = match (start, transport().clock()) {
+        (Some(start), Some(end)) => {
+            let ns = end.saturating_sub(start);
+            format!(" <{}.{:06}>", ns / 1_000_000_000, ns % 1_000_000_000 / 1000)
+        }
+        _ => String::new(),
+    };
+    debug!(target: TARGET, "fd {} {} = {}{}", fd, call, result, latency);
+}
+
+// Returns the host ioctl's result on success. On failure returns -1 and sets errno, like ioctl(2).
 #[no_mangle]
 pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
-    //info!("PXP cmd: {:?} Enter", &cmd);
+    let traced = traced(ioctl_info(cmd).map(|(_, family)| family));
+    let start = if traced { transport().clock() } else { None };
     let ret = match cmd {
         // Consumed by i915 driver's drm_gem_close_ioctl()
-        DRM_IOCTL_GEM_CLOSE => exec::<drm_gem_close_t>(fd, &cmd, arg),
+        DRM_IOCTL_GEM_CLOSE => drm_gem_close_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's drm_getmagic()
         DRM_IOCTL_GET_MAGIC => exec::<drm_auth>(fd, &cmd, arg),
         // Consumed by i915 driver's drm_authmagic()
@@ -1376,15 +3369,27 @@ pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
         // Consumed by i915 driver's i915_pxp_ops_ioctl()
         PRELIM_DRM_IOCTL_I915_PXP_OPS => exec2::<prelim_drm_i915_pxp_ops>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_create_ioctl()
-        DRM_IOCTL_I915_GEM_CREATE_EXT => exec2::<prelim_drm_i915_gem_create_ext>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_CREATE_EXT => i915_gem_create_ext_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_query_ioctl()
         DRM_IOCTL_I915_QUERY => exec2::<drm_i915_query>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_param_ioctl()
-        DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM => exec2::<drm_i915_gem_context_param>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM => exec2::<drm_i915_gem_context_getparam>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_param_ioctl()
         DRM_IOCTL_I915_GEM_CONTEXT_SETPARAM => exec2::<drm_i915_gem_context_param>(fd, &cmd, arg),
         // Consumed by i915 driver's drm_version()
         DRM_IOCTL_VERSION => exec2::<drm_version>(fd, &cmd, arg),
+        // Consumed by drm_getunique()
+        DRM_IOCTL_GET_UNIQUE => exec2::<drm_unique>(fd, &cmd, arg),
+        // Consumed by drm_getcap()
+        DRM_IOCTL_GET_CAP => exec::<drm_get_cap>(fd, &cmd, arg),
+        // Consumed by drm_setclientcap()
+        DRM_IOCTL_SET_CLIENT_CAP => exec::<drm_set_client_cap>(fd, &cmd, arg),
+        // Consumed by drm_setversion()
+        DRM_IOCTL_SET_VERSION => exec::<drm_set_version>(fd, &cmd, arg),
+        // Consumed by drm_gem_flink_ioctl()
+        DRM_IOCTL_GEM_FLINK => exec::<drm_gem_flink>(fd, &cmd, arg),
+        // Consumed by drm_gem_open_ioctl()
+        DRM_IOCTL_GEM_OPEN => drm_gem_open_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_getparam_ioctl()
         DRM_IOCTL_I915_GETPARAM => exec2::<drm_i915_getparam>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_context_create_ioctl()
@@ -1396,7 +3401,7 @@ pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
         // Consumed by i915 driver's i915_gem_vm_destroy_ioctl()
         DRM_IOCTL_I915_GEM_VM_DESTROY => exec2::<drm_i915_gem_vm_control>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_mmap_offset_ioctl()
-        DRM_IOCTL_I915_GEM_MMAP_OFFSET => exec::<drm_i915_gem_mmap_offset>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_MMAP_OFFSET => i915_gem_mmap_offset_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_context_reset_stats_ioctl()
         DRM_IOCTL_I915_GET_RESET_STATS => exec::<drm_i915_reset_stats>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_get_aperture_ioctl()
@@ -1411,10 +3416,20 @@ pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
         DRM_IOCTL_I915_GEM_USERPTR => i915_gem_userptr_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_get_tiling_ioctl()
         DRM_IOCTL_I915_GEM_GET_TILING => exec::<drm_i915_gem_get_tiling>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_set_tiling_ioctl()
+        DRM_IOCTL_I915_GEM_SET_TILING => exec::<drm_i915_gem_set_tiling>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_set_caching_ioctl()
+        DRM_IOCTL_I915_GEM_SET_CACHING => exec::<drm_i915_gem_caching>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_get_caching_ioctl()
+        DRM_IOCTL_I915_GEM_GET_CACHING => exec::<drm_i915_gem_caching>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_throttle_ioctl(), it takes no argument.
+        DRM_IOCTL_I915_GEM_THROTTLE => ioctl(fd, &cmd, ptr::null()),
+        // Consumed by i915 driver's i915_gem_create_ioctl()
+        DRM_IOCTL_I915_GEM_CREATE => i915_gem_create_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_wait_ioctl()
         DRM_IOCTL_I915_GEM_WAIT => exec::<drm_i915_gem_wait>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_context_destroy_ioctl()
-        DRM_IOCTL_I915_GEM_CONTEXT_DESTROY => exec::<drm_gem_close_t>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_CONTEXT_DESTROY => exec::<drm_i915_gem_context_destroy>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_reg_read_ioctl()
         DRM_IOCTL_I915_REG_READ => exec::<drm_i915_reg_read>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_busy_ioctl()
@@ -1422,7 +3437,7 @@ pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
         // Consumed by i915 driver's drm_prime_handle_to_fd_ioctl()
         DRM_IOCTL_PRIME_HANDLE_TO_FD => exec::<drm_prime_handle>(fd, &cmd, arg),
         // Consumed by i915 driver's drm_prime_fd_to_handle_ioctl()
-        DRM_IOCTL_PRIME_FD_TO_HANDLE => exec::<drm_prime_handle>(fd, &cmd, arg),
+        DRM_IOCTL_PRIME_FD_TO_HANDLE => drm_prime_fd_to_handle_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's intel_get_pipe_from_crtc_id_ioctl()
         DRM_IOCTL_I915_GET_PIPE_FROM_CRTC_ID => {
             exec::<drm_i915_get_pipe_from_crtc_id>(fd, &cmd, arg)
@@ -1432,36 +3447,80 @@ pub fn pxp_ioctl(fd: i32, cmd: u32, arg: *const u8) -> i32 {
         // Consumed by i915 driver's i915_gem_madvise_ioctl()
         DRM_IOCTL_I915_GEM_MADVISE => exec::<drm_i915_gem_madvise>(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_pread_ioctl()
-        DRM_IOCTL_I915_GEM_PREAD => exec2::<drm_i915_gem_pread>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_PREAD => i915_gem_pread_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_pwrite_ioctl()
-        DRM_IOCTL_I915_GEM_PWRITE => exec2::<drm_i915_gem_pwrite>(fd, &cmd, arg),
+        DRM_IOCTL_I915_GEM_PWRITE => i915_gem_pwrite_ioctl(fd, &cmd, arg),
         // Consumed by i915 driver's i915_gem_mmap_ioctl()
-        DRM_IOCTL_I915_GEM_MMAP => exec::<drm_i915_gem_mmap>(fd, &cmd, arg),
-        _ => drm_default_ioctl(fd, &cmd, arg),
-    }
-    .unwrap();
-    //info!("PXP cmd: {:?} Exit", &cmd);
-    ret
-}
-
-cfg_if::cfg_if! {
-    if #[cfg(feature = "occlum")] {
-        extern "C" {
-            fn occlum_ocall_device_ioctl(
-                ret: *mut i32,
-                fd: i32,  //c_int
-                request: i32,  //c_int
-                arg: u64,
-            ) -> sgx_status_t;
+        DRM_IOCTL_I915_GEM_MMAP => i915_gem_mmap_ioctl(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_vm_bind_ioctl()
+        PRELIM_DRM_IOCTL_I915_GEM_VM_BIND => exec2::<prelim_drm_i915_gem_vm_bind>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_gem_vm_unbind_ioctl()
+        PRELIM_DRM_IOCTL_I915_GEM_VM_UNBIND => {
+            exec2::<prelim_drm_i915_gem_vm_bind>(fd, &cmd, arg)
         }
-    } else {
-        extern "C" {
-            fn ocall_pxp_ioctl(
-                ret: *mut i32,
-                fd: i32,  //c_int
-                request: i32,  //c_int
-                arg: u64,
-            ) -> sgx_status_t;
+        // Consumed by i915 driver's i915_gem_wait_user_fence_ioctl()
+        PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE => {
+            i915_gem_wait_user_fence_ioctl(fd, &cmd, arg)
+        }
+        // Consumed by i915 driver's i915_uuid_register_ioctl()
+        PRELIM_DRM_IOCTL_I915_UUID_REGISTER => {
+            exec2::<prelim_drm_i915_uuid_control>(fd, &cmd, arg)
+        }
+        // Consumed by i915 driver's i915_uuid_unregister_ioctl()
+        PRELIM_DRM_IOCTL_I915_UUID_UNREGISTER => {
+            exec2::<prelim_drm_i915_uuid_control>(fd, &cmd, arg)
+        }
+        // Consumed by i915 driver's i915_perf_open_ioctl(), returns the stream fd.
+        DRM_IOCTL_I915_PERF_OPEN => exec2::<drm_i915_perf_open_param>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_perf_add_config_ioctl(), returns the config id.
+        DRM_IOCTL_I915_PERF_ADD_CONFIG => exec2::<drm_i915_perf_oa_config>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_perf_remove_config_ioctl()
+        DRM_IOCTL_I915_PERF_REMOVE_CONFIG => exec::<u64>(fd, &cmd, arg),
+        // Consumed by i915 driver's i915_perf_ioctl() on the stream fd, the argument is a value.
+        // Note: The samples are read() from the stream fd, which has no OCALL yet, see slib/README.md.
+        I915_PERF_IOCTL_ENABLE | I915_PERF_IOCTL_DISABLE | I915_PERF_IOCTL_CONFIG => {
+            ioctl(fd, &cmd, arg)
+        }
+        // Consumed by drm_syncobj_create_ioctl()
+        DRM_IOCTL_SYNCOBJ_CREATE => exec::<drm_syncobj_create>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_destroy_ioctl()
+        DRM_IOCTL_SYNCOBJ_DESTROY => exec::<drm_syncobj_destroy>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_handle_to_fd_ioctl()
+        DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD => exec::<drm_syncobj_handle>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_fd_to_handle_ioctl()
+        DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE => exec::<drm_syncobj_handle>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_wait_ioctl()
+        DRM_IOCTL_SYNCOBJ_WAIT => exec2::<drm_syncobj_wait>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_timeline_wait_ioctl()
+        DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT => exec2::<drm_syncobj_timeline_wait>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_reset_ioctl()
+        DRM_IOCTL_SYNCOBJ_RESET => exec2::<drm_syncobj_array>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_signal_ioctl()
+        DRM_IOCTL_SYNCOBJ_SIGNAL => exec2::<drm_syncobj_array>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_timeline_signal_ioctl()
+        DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL => exec2::<drm_syncobj_timeline_array>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_query_ioctl()
+        DRM_IOCTL_SYNCOBJ_QUERY => exec2::<drm_syncobj_timeline_query>(fd, &cmd, arg),
+        // Consumed by drm_syncobj_transfer_ioctl()
+        DRM_IOCTL_SYNCOBJ_TRANSFER => exec::<drm_syncobj_transfer>(fd, &cmd, arg),
+        _ => drm_default_ioctl(fd, &cmd, arg),
+    };
+    if traced {
+        trace_ioctl(fd, cmd, arg, &ret, start);
+    }
+    match ret {
+        Ok(ret) => ret,
+        Err(e) => {
+            match e {
+                // The kernel's answer, e.g. EAGAIN or ETIME of the polling ioctls.
+                Error::HostIoctl(_) => debug!("PXP cmd: {:?} failed: {}", cmd, e),
+                _ => error!("PXP cmd: {:?} failed: {}", cmd, e),
+            }
+            set_errno(e.errno());
+            -1
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
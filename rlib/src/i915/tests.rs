@@ -0,0 +1,1470 @@
+extern crate std;
+
+use super::*;
+use crate::error::{errno, EFAULT, EINVAL};
+use crate::fake_i915::FakeI915;
+use crate::mmap::{pxp_mmap, pxp_munmap};
+use crate::mock::{
+    alloc_untrusted, in_arena, set_driver, MockTransport, ScriptedDriver, TEST_LOCK,
+};
+use crate::transport::{set_transport, Transport};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+// pxp_ioctl() on an argument in trusted memory. Returns the errno on failure.
+fn call<T>(fd: i32, cmd: u32, arg: &mut T) -> Result<i32, i32> {
+    match pxp_ioctl(fd, cmd, arg as *mut T as *const u8) {
+        ret if ret < 0 => Err(errno()),
+        ret => Ok(ret),
+    }
+}
+
+// Install a fresh fake driver. The returned clone shares its state.
+fn fake_i915() -> FakeI915 {
+    let fake = FakeI915::new();
+    set_driver(Box::new(fake.clone()));
+    fake
+}
+
+fn zeroed<T>() -> T {
+    unsafe { mem::zeroed() }
+}
+
+#[test]
+fn version_two_pass() {
+    let _lock = TEST_LOCK.lock();
+    let _fake = fake_i915();
+    let mut version = zeroed::<drm_version>();
+    assert_eq!(call(10, DRM_IOCTL_VERSION, &mut version), Ok(0));
+    assert_eq!(
+        (version.name_len, version.date_len, version.desc_len),
+        (4, 8, 14)
+    );
+
+    let mut name = vec![0u8; version.name_len as usize];
+    let mut date = vec![0u8; version.date_len as usize];
+    let mut desc = vec![0u8; version.desc_len as usize];
+    version.name = name.as_mut_ptr();
+    version.date = date.as_mut_ptr();
+    version.desc = desc.as_mut_ptr();
+    assert_eq!(call(10, DRM_IOCTL_VERSION, &mut version), Ok(0));
+    assert_eq!(
+        (
+            version.version_major,
+            version.version_minor,
+            version.version_patchlevel
+        ),
+        (1, 6, 0)
+    );
+    assert_eq!(&name[..], b"i915");
+    assert_eq!(&date[..], b"20201103");
+    assert_eq!(&desc[..], b"Intel Graphics");
+    // The strings are left where the application put them.
+    assert_eq!(version.name, name.as_ptr());
+}
+
+#[test]
+fn query_two_pass() {
+    let _lock = TEST_LOCK.lock();
+    let fake = fake_i915();
+    let payload: Vec<u8> = (0..24).collect();
+    fake.state().query_items.insert(1, payload.clone());
+
+    let mut item = drm_i915_query_item {
+        query_id: 1,
+        length: 0,
+        flags: 0,
+        data_ptr: 0,
+    };
+    let mut query = drm_i915_query {
+        num_items: 1,
+        flags: 0,
+        items_ptr: &mut item as *mut _ as u64,
+    };
+    assert_eq!(call(11, DRM_IOCTL_I915_QUERY, &mut query), Ok(0));
+    assert_eq!(item.length, payload.len() as i32);
+    assert_eq!(item.data_ptr, 0);
+
+    let mut data = vec![0u8; item.length as usize];
+    item.data_ptr = data.as_mut_ptr() as u64;
+    assert_eq!(call(11, DRM_IOCTL_I915_QUERY, &mut query), Ok(0));
+    assert_eq!(item.length, payload.len() as i32);
+    assert_eq!(data, payload);
+    assert_eq!(query.items_ptr, &item as *const _ as u64);
+
+    // An unknown query is reported in the length of the item, not by the ioctl.
+    item.query_id = 2;
+    assert_eq!(call(11, DRM_IOCTL_I915_QUERY, &mut query), Ok(0));
+    assert_eq!(item.length, -EINVAL);
+}
+
+#[test]
+fn gem_create_then_pwrite() {
+    let _lock = TEST_LOCK.lock();
+    let fake = fake_i915();
+    let fd = 12;
+    let mut create = drm_i915_gem_create {
+        size: 4096,
+        handle: 0,
+        pad: 0,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_CREATE, &mut create), Ok(0));
+    assert_ne!(create.handle, 0);
+
+    let payload: Vec<u8> = (100..116).collect();
+    let mut pwrite = drm_i915_gem_pwrite {
+        handle: create.handle,
+        pad: 0,
+        offset: 8,
+        size: payload.len() as u64,
+        data_ptr: payload.as_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_PWRITE, &mut pwrite), Ok(0));
+    assert_eq!(
+        &fake.state().objects[&(fd, create.handle)].contents[8..24],
+        &payload[..]
+    );
+
+    let mut data = vec![0u8; payload.len()];
+    let mut pread = drm_i915_gem_pread {
+        handle: create.handle,
+        pad: 0,
+        offset: 8,
+        size: data.len() as u64,
+        data_ptr: data.as_mut_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_PREAD, &mut pread), Ok(0));
+    assert_eq!(data, payload);
+
+    // Past the end of the object recorded by GEM_CREATE: rejected before reaching the driver.
+    pwrite.offset = 4090;
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_PWRITE, &mut pwrite),
+        Err(EINVAL)
+    );
+    assert_eq!(
+        fake.state().objects[&(fd, create.handle)].contents.len(),
+        24
+    );
+}
+
+#[test]
+fn rejected_extension_chain_is_freed() {
+    let _lock = TEST_LOCK.lock();
+    let fake = fake_i915();
+    let regions = [prelim_drm_i915_gem_memory_class_instance {
+        memory_class: 1,
+        memory_instance: 0,
+    }];
+    let mut unknown = zeroed::<i915_user_extension>();
+    unknown.name = PRELIM_I915_USER_EXT | 0x7f;
+    let mut setparam = prelim_drm_i915_gem_create_ext_setparam {
+        base: zeroed(),
+        param: prelim_drm_i915_gem_object_param {
+            handle: 0,
+            size: regions.len() as u32,
+            param: 1 << 32 | 1,
+            data: regions.as_ptr() as u64,
+        },
+    };
+    setparam.base.name = PRELIM_I915_USER_EXT | 1;
+    setparam.base.next_extension = &unknown as *const _ as u64;
+    let mut create = prelim_drm_i915_gem_create_ext {
+        size: 4096,
+        handle: 0,
+        pad: 0,
+        extensions: &setparam as *const _ as u64,
+    };
+    let live = crate::memory::live_allocations();
+    // The second extension is rejected once the first one and its regions are allocated.
+    assert_eq!(
+        call(13, DRM_IOCTL_I915_GEM_CREATE_EXT, &mut create),
+        Err(EINVAL)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert!(fake.state().objects.is_empty());
+}
+
+#[test]
+fn vm_bind_sync_fence_and_set_pat() {
+    let _lock = TEST_LOCK.lock();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    set_driver(Box::new(ScriptedDriver::new().on(
+        PRELIM_DRM_IOCTL_I915_GEM_VM_BIND,
+        move |_, arg| {
+            let bind = snapshot::<prelim_drm_i915_gem_vm_bind>(arg as u64);
+            let mut ext = bind.extensions;
+            while ext != 0 {
+                let base = snapshot::<i915_user_extension>(ext);
+                let value = match base.name & PRELIM_I915_USER_EXT_MASK {
+                    0 => snapshot::<prelim_drm_i915_vm_bind_ext_sync_fence>(ext).addr,
+                    2 => snapshot::<prelim_drm_i915_vm_bind_ext_set_pat>(ext).pat_index,
+                    _ => return Err(EINVAL),
+                };
+                record.lock().push((base.name, value));
+                ext = base.next_extension;
+            }
+            Ok(0)
+        },
+    )));
+
+    let fence = alloc_untrusted(mem::size_of::<u64>(), mem::size_of::<u64>()) as u64;
+    let mut set_pat = prelim_drm_i915_vm_bind_ext_set_pat {
+        base: zeroed(),
+        pat_index: 3,
+    };
+    set_pat.base.name = PRELIM_I915_USER_EXT | 2;
+    let mut sync_fence = prelim_drm_i915_vm_bind_ext_sync_fence {
+        base: zeroed(),
+        addr: fence,
+        val: 1,
+    };
+    sync_fence.base.name = PRELIM_I915_USER_EXT;
+    sync_fence.base.next_extension = &set_pat as *const _ as u64;
+    let mut bind = zeroed::<prelim_drm_i915_gem_vm_bind>();
+    bind.extensions = &sync_fence as *const _ as u64;
+    assert_eq!(
+        call(14, PRELIM_DRM_IOCTL_I915_GEM_VM_BIND, &mut bind),
+        Ok(0)
+    );
+    assert_eq!(
+        &seen.lock()[..],
+        &[(PRELIM_I915_USER_EXT, fence), (PRELIM_I915_USER_EXT | 2, 3)]
+    );
+
+    // The kernel can't signal a fence inside enclave.
+    let trusted = 0u64;
+    let mut in_enclave = prelim_drm_i915_vm_bind_ext_sync_fence {
+        base: zeroed(),
+        addr: &trusted as *const _ as u64,
+        val: 1,
+    };
+    in_enclave.base.name = PRELIM_I915_USER_EXT;
+    bind.extensions = &in_enclave as *const _ as u64;
+    seen.lock().clear();
+    assert_eq!(
+        call(14, PRELIM_DRM_IOCTL_I915_GEM_VM_BIND, &mut bind),
+        Err(EINVAL)
+    );
+    assert!(seen.lock().is_empty());
+}
+
+// The _IOC_NR() and _IOC_SIZE() macros of the kernel.
+fn ioc_nr(cmd: u32) -> u32 {
+    cmd & 0xff
+}
+
+fn ioc_size(cmd: u32) -> usize {
+    ((cmd >> 16) & 0x3fff) as usize
+}
+
+#[test]
+fn prelim_vm_ioctl_numbers() {
+    const DRM_COMMAND_BASE: u32 = 0x40;
+    let table = [
+        (
+            PRELIM_DRM_IOCTL_I915_GEM_VM_BIND,
+            0x5d,
+            mem::size_of::<prelim_drm_i915_gem_vm_bind>(),
+        ),
+        (
+            PRELIM_DRM_IOCTL_I915_GEM_VM_UNBIND,
+            0x5c,
+            mem::size_of::<prelim_drm_i915_gem_vm_bind>(),
+        ),
+        (
+            PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE,
+            0x5a,
+            mem::size_of::<prelim_drm_i915_gem_wait_user_fence>(),
+        ),
+        (
+            PRELIM_DRM_IOCTL_I915_UUID_REGISTER,
+            0x58,
+            mem::size_of::<prelim_drm_i915_uuid_control>(),
+        ),
+        (
+            PRELIM_DRM_IOCTL_I915_UUID_UNREGISTER,
+            0x57,
+            mem::size_of::<prelim_drm_i915_uuid_control>(),
+        ),
+    ];
+    for (cmd, nr, size) in table {
+        assert_eq!(ioc_nr(cmd), DRM_COMMAND_BASE + nr, "{}", describe_ioc(cmd));
+        assert_eq!(ioc_size(cmd), size, "{}", describe_ioc(cmd));
+    }
+}
+
+fn create(fd: i32, size: u64) -> u32 {
+    let mut create = drm_i915_gem_create {
+        size,
+        handle: 0,
+        pad: 0,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_CREATE, &mut create), Ok(0));
+    create.handle
+}
+
+fn round_trip_version(_: &FakeI915, fd: i32) {
+    let mut name = [0u8; 4];
+    let mut date = [0u8; 8];
+    let mut desc = [0u8; 5];
+    let mut version = drm_version {
+        version_major: -1,
+        version_minor: -1,
+        version_patchlevel: -1,
+        name_len: name.len() as u64,
+        name: name.as_mut_ptr(),
+        date_len: date.len() as u64,
+        date: date.as_mut_ptr(),
+        desc_len: desc.len() as u64,
+        desc: desc.as_mut_ptr(),
+    };
+    assert_eq!(call(fd, DRM_IOCTL_VERSION, &mut version), Ok(0));
+    assert_eq!(version.version_major, 1);
+    assert_eq!(version.version_minor, 6);
+    assert_eq!(version.version_patchlevel, 0);
+    assert_eq!(version.name_len, 4);
+    assert_eq!(version.name, name.as_ptr());
+    assert_eq!(version.date_len, 8);
+    assert_eq!(version.date, date.as_ptr());
+    // Truncated to the buffer, the full length is reported.
+    assert_eq!(version.desc_len, 14);
+    assert_eq!(version.desc, desc.as_ptr());
+    assert_eq!((&name, &date, &desc), (b"i915", b"20201103", b"Intel"));
+}
+
+fn round_trip_getparam(fake: &FakeI915, fd: i32) {
+    fake.state().params.insert(23, 42);
+    let mut value = 0i32;
+    let mut getparam = drm_i915_getparam {
+        param: 23,
+        value: &mut value,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GETPARAM, &mut getparam), Ok(0));
+    assert_eq!(getparam.param, 23);
+    assert_eq!(getparam.value, &mut value as *mut i32);
+    assert_eq!(value, 42);
+}
+
+fn round_trip_gem_create(fake: &FakeI915, fd: i32) {
+    let mut create = drm_i915_gem_create {
+        size: 100,
+        handle: 0,
+        pad: 0,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_CREATE, &mut create), Ok(0));
+    assert_eq!(create.size, 4096);
+    assert_eq!(create.handle, 1);
+    assert_eq!(create.pad, 0);
+    assert_eq!(fake.state().objects[&(fd, 1)].size, 4096);
+}
+
+fn round_trip_gem_create_ext(fake: &FakeI915, fd: i32) {
+    let regions = [
+        prelim_drm_i915_gem_memory_class_instance {
+            memory_class: 1,
+            memory_instance: 0,
+        },
+        prelim_drm_i915_gem_memory_class_instance {
+            memory_class: 0,
+            memory_instance: 0,
+        },
+    ];
+    let mut setparam = prelim_drm_i915_gem_create_ext_setparam {
+        base: zeroed(),
+        param: prelim_drm_i915_gem_object_param {
+            handle: 0,
+            size: regions.len() as u32,
+            param: 1 << 32 | 1,
+            data: regions.as_ptr() as u64,
+        },
+    };
+    setparam.base.name = PRELIM_I915_USER_EXT | 1;
+    let mut create = prelim_drm_i915_gem_create_ext {
+        size: 8192,
+        handle: 0,
+        pad: 0,
+        extensions: &setparam as *const _ as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_CREATE_EXT, &mut create), Ok(0));
+    assert_eq!(create.size, 8192);
+    assert_eq!(create.handle, 1);
+    assert_eq!(create.pad, 0);
+    assert_eq!(create.extensions, &setparam as *const _ as u64);
+    assert_eq!(setparam.base.next_extension, 0);
+    assert_eq!(setparam.param.data, regions.as_ptr() as u64);
+    assert_eq!(
+        fake.state().objects[&(fd, 1)].placements,
+        vec![(1, 0), (0, 0)]
+    );
+}
+
+fn round_trip_gem_close(fake: &FakeI915, fd: i32) {
+    let handle = create(fd, 4096);
+    let mut close = drm_gem_close_t { handle, pad: 0 };
+    assert_eq!(call(fd, DRM_IOCTL_GEM_CLOSE, &mut close), Ok(0));
+    assert_eq!((close.handle, close.pad), (handle, 0));
+    assert!(fake.state().objects.is_empty());
+}
+
+fn round_trip_gem_pread(fake: &FakeI915, fd: i32) {
+    let handle = create(fd, 4096);
+    fake.state()
+        .objects
+        .get_mut(&(fd, handle))
+        .unwrap()
+        .contents = (0..64).collect();
+    let mut data = [0u8; 16];
+    let mut pread = drm_i915_gem_pread {
+        handle,
+        pad: 0,
+        offset: 32,
+        size: data.len() as u64,
+        data_ptr: data.as_mut_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_PREAD, &mut pread), Ok(0));
+    assert_eq!(pread.handle, handle);
+    assert_eq!(pread.pad, 0);
+    assert_eq!(pread.offset, 32);
+    assert_eq!(pread.size, 16);
+    assert_eq!(pread.data_ptr, data.as_ptr() as u64);
+    assert_eq!(data.to_vec(), (32..48).collect::<Vec<u8>>());
+}
+
+fn round_trip_gem_pwrite(fake: &FakeI915, fd: i32) {
+    let handle = create(fd, 4096);
+    let data: Vec<u8> = (0..16).collect();
+    let mut pwrite = drm_i915_gem_pwrite {
+        handle,
+        pad: 0,
+        offset: 4,
+        size: data.len() as u64,
+        data_ptr: data.as_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_PWRITE, &mut pwrite), Ok(0));
+    assert_eq!(pwrite.handle, handle);
+    assert_eq!(pwrite.pad, 0);
+    assert_eq!(pwrite.offset, 4);
+    assert_eq!(pwrite.size, 16);
+    assert_eq!(pwrite.data_ptr, data.as_ptr() as u64);
+    assert_eq!(fake.state().objects[&(fd, handle)].contents[4..], data[..]);
+}
+
+fn round_trip_query(fake: &FakeI915, fd: i32) {
+    fake.state().query_items.insert(3, vec![7; 12]);
+    let mut data = [0u8; 16];
+    let mut items = [
+        drm_i915_query_item {
+            query_id: 3,
+            length: data.len() as i32,
+            flags: 0,
+            data_ptr: data.as_mut_ptr() as u64,
+        },
+        drm_i915_query_item {
+            query_id: 4,
+            length: 0,
+            flags: 0,
+            data_ptr: 0,
+        },
+    ];
+    let mut query = drm_i915_query {
+        num_items: items.len() as u32,
+        flags: 0,
+        items_ptr: items.as_mut_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_QUERY, &mut query), Ok(0));
+    assert_eq!(query.num_items, 2);
+    assert_eq!(query.flags, 0);
+    assert_eq!(query.items_ptr, items.as_ptr() as u64);
+    assert_eq!(items[0].query_id, 3);
+    assert_eq!(items[0].length, 12);
+    assert_eq!(items[0].flags, 0);
+    assert_eq!(items[0].data_ptr, data.as_ptr() as u64);
+    assert_eq!(data, [7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 0, 0, 0, 0]);
+    assert_eq!(items[1].query_id, 4);
+    assert_eq!(items[1].length, -EINVAL);
+    assert_eq!(items[1].data_ptr, 0);
+}
+
+fn round_trip_context_create(fake: &FakeI915, fd: i32) {
+    // i915_context_param_engines with two engines: no extensions, then (class, instance) pairs.
+    let engines: [u64; 2] = [0, 1 << 16];
+    let mut setparam = drm_i915_gem_context_create_ext_setparam {
+        base: zeroed(),
+        param: drm_i915_gem_context_param {
+            ctx_id: 0,
+            size: mem::size_of_val(&engines) as u32,
+            param: I915_CONTEXT_PARAM_ENGINES,
+            value: engines.as_ptr() as u64,
+        },
+    };
+    setparam.base.name = 0;
+    let mut create = drm_i915_gem_context_create_ext {
+        ctx_id: 0,
+        // I915_CONTEXT_CREATE_FLAGS_USE_EXTENSIONS
+        flags: 1,
+        extensions: &setparam as *const _ as u64,
+    };
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT, &mut create),
+        Ok(0)
+    );
+    assert_eq!(create.ctx_id, 1);
+    assert_eq!(create.flags, 1);
+    assert_eq!(create.extensions, &setparam as *const _ as u64);
+    assert_eq!(setparam.param.value, engines.as_ptr() as u64);
+    assert!(fake.state().contexts.contains(&(fd, 1)));
+}
+
+fn round_trip_context_destroy(fake: &FakeI915, fd: i32) {
+    fake.state().contexts.insert((fd, 5));
+    let mut destroy = drm_gem_close_t { handle: 5, pad: 0 };
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_CONTEXT_DESTROY, &mut destroy),
+        Ok(0)
+    );
+    assert_eq!((destroy.handle, destroy.pad), (5, 0));
+    assert!(fake.state().contexts.is_empty());
+}
+
+fn round_trip_vm_create(fake: &FakeI915, fd: i32) {
+    let mut control = drm_i915_gem_vm_control {
+        extensions: 0,
+        flags: 0,
+        vm_id: 0,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_VM_CREATE, &mut control), Ok(0));
+    assert_eq!(control.extensions, 0);
+    assert_eq!(control.flags, 0);
+    assert_eq!(control.vm_id, 1);
+    assert!(fake.state().vms.contains(&(fd, 1)));
+}
+
+fn round_trip_vm_destroy(fake: &FakeI915, fd: i32) {
+    fake.state().vms.insert((fd, 9));
+    let mut control = drm_i915_gem_vm_control {
+        extensions: 0,
+        flags: 0,
+        vm_id: 9,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_VM_DESTROY, &mut control), Ok(0));
+    assert_eq!(control.extensions, 0);
+    assert_eq!(control.flags, 0);
+    assert_eq!(control.vm_id, 9);
+    assert!(fake.state().vms.is_empty());
+}
+
+fn execbuffer2(fake: &FakeI915, fd: i32, cmd: u32) {
+    let handles = [create(fd, 4096), create(fd, 4096)];
+    let mut objects: [drm_i915_gem_exec_object2; 2] = zeroed();
+    objects[0].handle = handles[0];
+    objects[1].handle = handles[1];
+    objects[1].flags = 0x10;
+    let fences = [drm_i915_gem_exec_fence {
+        handle: 3,
+        flags: 1,
+    }];
+    // I915_EXEC_FENCE_OUT
+    let fence_out = 1 << 17;
+    let mut execbuffer = drm_i915_gem_execbuffer2 {
+        buffers_ptr: objects.as_mut_ptr() as u64,
+        buffer_count: objects.len() as u32,
+        batch_start_offset: 64,
+        batch_len: 128,
+        dr1: 0,
+        dr4: 0,
+        num_cliprects: fences.len() as u32,
+        cliprects_ptr: fences.as_ptr() as u64,
+        flags: I915_EXEC_FENCE_ARRAY | fence_out,
+        rsvd1: 0,
+        rsvd2: 0,
+    };
+    assert_eq!(call(fd, cmd, &mut execbuffer), Ok(0));
+    assert_eq!(execbuffer.buffers_ptr, objects.as_ptr() as u64);
+    assert_eq!(execbuffer.buffer_count, 2);
+    assert_eq!(execbuffer.batch_start_offset, 64);
+    assert_eq!(execbuffer.batch_len, 128);
+    assert_eq!((execbuffer.dr1, execbuffer.dr4), (0, 0));
+    assert_eq!(execbuffer.num_cliprects, 1);
+    assert_eq!(execbuffer.cliprects_ptr, fences.as_ptr() as u64);
+    assert_eq!(execbuffer.flags, I915_EXEC_FENCE_ARRAY | fence_out);
+    assert_eq!(execbuffer.rsvd1, 0);
+    // Only the _WR variant returns the out fence.
+    if cmd == DRM_IOCTL_I915_GEM_EXECBUFFER2_WR {
+        assert_eq!(execbuffer.rsvd2, 1000 << 32);
+    } else {
+        assert_eq!(execbuffer.rsvd2, 0);
+    }
+    for (object, handle) in objects.iter().zip(handles) {
+        assert_eq!(object.handle, handle);
+        assert_eq!(object.relocation_count, 0);
+        assert_eq!(object.relocs_ptr, 0);
+        assert_eq!(object.alignment, 0);
+        assert_eq!(object.offset, (handle as u64) << 32);
+        assert_eq!((object.rsvd1, object.rsvd2), (0, 0));
+    }
+    assert_eq!((objects[0].flags, objects[1].flags), (0, 0x10));
+    assert_eq!(fake.state().execbuffers[0].handles, handles);
+}
+
+fn round_trip_execbuffer2(fake: &FakeI915, fd: i32) {
+    execbuffer2(fake, fd, DRM_IOCTL_I915_GEM_EXECBUFFER2);
+}
+
+fn round_trip_execbuffer2_wr(fake: &FakeI915, fd: i32) {
+    execbuffer2(fake, fd, DRM_IOCTL_I915_GEM_EXECBUFFER2_WR);
+}
+
+fn round_trip_pxp_ops(fake: &FakeI915, fd: i32) {
+    // PRELIM_DRM_I915_PXP_ACTION_SET_SESSION_STATUS with PRELIM_DRM_I915_PXP_REQ_SESSION_ID_INIT
+    let mut status = prelim_drm_i915_pxp_set_session_status_params {
+        pxp_tag: 0xff,
+        session_type: 1,
+        session_mode: 2,
+        req_session_state: 0,
+    };
+    let mut ops = prelim_drm_i915_pxp_ops {
+        action: 0,
+        status: 0xff,
+        params: &mut status as *mut _ as u64,
+    };
+    assert_eq!(call(fd, PRELIM_DRM_IOCTL_I915_PXP_OPS, &mut ops), Ok(0));
+    assert_eq!({ ops.action }, 0);
+    assert_eq!({ ops.status }, 0);
+    assert_eq!({ ops.params }, &status as *const _ as u64);
+    assert_eq!(
+        (
+            { status.pxp_tag },
+            { status.session_type },
+            { status.session_mode },
+            { status.req_session_state }
+        ),
+        (0, 1, 2, 0)
+    );
+    assert_eq!(fake.state().sessions[&0].session_mode, 2);
+
+    // PRELIM_DRM_I915_PXP_ACTION_TEE_IO_MESSAGE, echoed by the fake firmware
+    let msg_in = [1u8, 2, 3, 4, 5, 6];
+    let mut msg_out = [0u8; 8];
+    let mut io = prelim_drm_i915_pxp_tee_io_message_params {
+        msg_in: msg_in.as_ptr() as u64,
+        msg_in_size: msg_in.len() as u32,
+        msg_out: msg_out.as_mut_ptr() as u64,
+        msg_out_buf_size: msg_out.len() as u32,
+        msg_out_ret_size: 0,
+    };
+    let mut ops = prelim_drm_i915_pxp_ops {
+        action: 1,
+        status: 0xff,
+        params: &mut io as *mut _ as u64,
+    };
+    assert_eq!(call(fd, PRELIM_DRM_IOCTL_I915_PXP_OPS, &mut ops), Ok(0));
+    assert_eq!(({ ops.action }, { ops.status }), (1, 0));
+    assert_eq!({ ops.params }, &io as *const _ as u64);
+    assert_eq!({ io.msg_in }, msg_in.as_ptr() as u64);
+    assert_eq!({ io.msg_in_size }, 6);
+    assert_eq!({ io.msg_out }, msg_out.as_ptr() as u64);
+    assert_eq!({ io.msg_out_buf_size }, 8);
+    assert_eq!({ io.msg_out_ret_size }, 6);
+    assert_eq!(msg_out, [1, 2, 3, 4, 5, 6, 0, 0]);
+
+    // PRELIM_DRM_I915_PXP_ACTION_QUERY_PXP_TAG of the reserved session
+    let mut tag = prelim_drm_i915_pxp_query_tag {
+        session_is_alive: 0xff,
+        pxp_tag: 0,
+    };
+    let mut ops = prelim_drm_i915_pxp_ops {
+        action: 2,
+        status: 0xff,
+        params: &mut tag as *mut _ as u64,
+    };
+    assert_eq!(call(fd, PRELIM_DRM_IOCTL_I915_PXP_OPS, &mut ops), Ok(0));
+    assert_eq!(({ ops.action }, { ops.status }), (2, 0));
+    assert_eq!({ ops.params }, &tag as *const _ as u64);
+    assert_eq!(({ tag.session_is_alive }, { tag.pxp_tag }), (0, 0));
+}
+
+// Set up the fake driver, make the call on the given fd and check every field of the argument.
+type RoundTrip = fn(&FakeI915, i32);
+
+// One row per command modelled by FakeI915: the trusted argument goes t2u, through the fake driver
+// and back u2t, then every field of it is checked.
+const ROUND_TRIPS: [(u32, RoundTrip); 15] = [
+    (DRM_IOCTL_VERSION, round_trip_version),
+    (DRM_IOCTL_I915_GETPARAM, round_trip_getparam),
+    (DRM_IOCTL_I915_GEM_CREATE, round_trip_gem_create),
+    (DRM_IOCTL_I915_GEM_CREATE_EXT, round_trip_gem_create_ext),
+    (DRM_IOCTL_GEM_CLOSE, round_trip_gem_close),
+    (DRM_IOCTL_I915_GEM_PREAD, round_trip_gem_pread),
+    (DRM_IOCTL_I915_GEM_PWRITE, round_trip_gem_pwrite),
+    (DRM_IOCTL_I915_QUERY, round_trip_query),
+    (
+        DRM_IOCTL_I915_GEM_CONTEXT_CREATE_EXT,
+        round_trip_context_create,
+    ),
+    (
+        DRM_IOCTL_I915_GEM_CONTEXT_DESTROY,
+        round_trip_context_destroy,
+    ),
+    (DRM_IOCTL_I915_GEM_VM_CREATE, round_trip_vm_create),
+    (DRM_IOCTL_I915_GEM_VM_DESTROY, round_trip_vm_destroy),
+    (DRM_IOCTL_I915_GEM_EXECBUFFER2, round_trip_execbuffer2),
+    (DRM_IOCTL_I915_GEM_EXECBUFFER2_WR, round_trip_execbuffer2_wr),
+    (PRELIM_DRM_IOCTL_I915_PXP_OPS, round_trip_pxp_ops),
+];
+
+#[test]
+fn round_trip() {
+    let _lock = TEST_LOCK.lock();
+    for (i, (cmd, row)) in ROUND_TRIPS.iter().enumerate() {
+        let fake = fake_i915();
+        let live = crate::memory::live_allocations();
+        row(&fake, 100 + i as i32);
+        assert_eq!(
+            crate::memory::live_allocations(),
+            live,
+            "{}",
+            describe_ioc(*cmd)
+        );
+    }
+}
+
+// Every argument and buffer the driver is handed must be untrusted memory, a pointer inside enclave
+// is recorded by FakeI915 whatever the result of the call.
+#[test]
+fn round_trip_without_violations() {
+    let _lock = TEST_LOCK.lock();
+    for (i, (cmd, row)) in ROUND_TRIPS.iter().enumerate() {
+        let fake = fake_i915();
+        row(&fake, 200 + i as i32);
+        let violations = fake.state().violations.clone();
+        assert!(
+            violations.is_empty(),
+            "{}: {:?}",
+            describe_ioc(*cmd),
+            violations
+        );
+    }
+}
+
+const GUARD: u8 = 0xa5;
+
+// A trusted buffer followed by guard bytes, to catch a copy past its end.
+#[repr(C, align(8))]
+struct Guarded<const N: usize> {
+    buf: [u8; N],
+    guard: [u8; 64],
+}
+
+impl<const N: usize> Guarded<N> {
+    fn new() -> Guarded<N> {
+        Guarded {
+            buf: [0; N],
+            guard: [GUARD; 64],
+        }
+    }
+
+    fn ptr(&mut self) -> u64 {
+        self.buf.as_mut_ptr() as u64
+    }
+
+    fn intact(&self) -> bool {
+        self.guard.iter().all(|&b| b == GUARD)
+    }
+}
+
+// Answer `cmd` with a handler that lies in the untrusted copy of the argument, then check that the
+// call fails with `errno` and leaves no untrusted memory behind.
+fn hostile<F>(fd: i32, cmd: u32, handler: F, call: impl FnOnce(i32) -> Result<i32, i32>, errno: i32)
+where
+    F: FnMut(i32, *mut u8) -> Result<i32, i32> + Send + 'static,
+{
+    set_driver(Box::new(ScriptedDriver::new().on(cmd, handler)));
+    let live = crate::memory::live_allocations();
+    assert_eq!(call(fd), Err(errno));
+    assert_eq!(crate::memory::live_allocations(), live);
+}
+
+#[test]
+fn hostile_msg_out_ret_size() {
+    let _lock = TEST_LOCK.lock();
+    let msg_in = [1u8; 8];
+    let mut msg_out = Guarded::<8>::new();
+    let mut io = prelim_drm_i915_pxp_tee_io_message_params {
+        msg_in: msg_in.as_ptr() as u64,
+        msg_in_size: msg_in.len() as u32,
+        msg_out: msg_out.ptr(),
+        msg_out_buf_size: 8,
+        msg_out_ret_size: 0,
+    };
+    let mut ops = prelim_drm_i915_pxp_ops {
+        action: 1,
+        status: 0xff,
+        params: &mut io as *mut _ as u64,
+    };
+    hostile(
+        20,
+        PRELIM_DRM_IOCTL_I915_PXP_OPS,
+        |_, arg| {
+            let ops = snapshot::<prelim_drm_i915_pxp_ops>(arg as u64);
+            let io = ops.params as *mut prelim_drm_i915_pxp_tee_io_message_params;
+            unsafe {
+                ptr::write_bytes((*io).msg_out as *mut u8, 0xee, 8);
+                (*io).msg_out_ret_size = 0x10000;
+            }
+            Ok(0)
+        },
+        |fd| call(fd, PRELIM_DRM_IOCTL_I915_PXP_OPS, &mut ops),
+        EFAULT,
+    );
+    assert!(msg_out.intact());
+    assert_eq!({ io.msg_out_ret_size }, 0);
+    assert_eq!({ ops.status }, 0xff);
+}
+
+#[test]
+fn hostile_query_item_length() {
+    let _lock = TEST_LOCK.lock();
+    let mut data = Guarded::<16>::new();
+    let mut item = drm_i915_query_item {
+        query_id: 1,
+        length: 16,
+        flags: 0,
+        data_ptr: data.ptr(),
+    };
+    let mut query = drm_i915_query {
+        num_items: 1,
+        flags: 0,
+        items_ptr: &mut item as *mut _ as u64,
+    };
+    hostile(
+        21,
+        DRM_IOCTL_I915_QUERY,
+        |_, arg| {
+            let query = snapshot::<drm_i915_query>(arg as u64);
+            let item = query.items_ptr as *mut drm_i915_query_item;
+            unsafe {
+                ptr::write_bytes((*item).data_ptr as *mut u8, 0xee, 16);
+                (*item).length = 4096;
+            }
+            Ok(0)
+        },
+        |fd| call(fd, DRM_IOCTL_I915_QUERY, &mut query),
+        EFAULT,
+    );
+    assert!(data.intact());
+    assert_eq!(item.length, 16);
+}
+
+#[test]
+fn hostile_relocation_count() {
+    let _lock = TEST_LOCK.lock();
+    let mut relocs = Guarded::<{ mem::size_of::<drm_i915_gem_relocation_entry>() }>::new();
+    let mut object = zeroed::<drm_i915_gem_exec_object2>();
+    object.handle = 1;
+    object.relocation_count = 1;
+    object.relocs_ptr = relocs.ptr();
+    let mut execbuffer = zeroed::<drm_i915_gem_execbuffer2>();
+    execbuffer.buffers_ptr = &mut object as *mut _ as u64;
+    execbuffer.buffer_count = 1;
+    hostile(
+        22,
+        DRM_IOCTL_I915_GEM_EXECBUFFER2_WR,
+        |_, arg| {
+            let execbuffer = snapshot::<drm_i915_gem_execbuffer2>(arg as u64);
+            let object = execbuffer.buffers_ptr as *mut drm_i915_gem_exec_object2;
+            unsafe { (*object).relocation_count = u32::MAX };
+            Ok(0)
+        },
+        |fd| call(fd, DRM_IOCTL_I915_GEM_EXECBUFFER2_WR, &mut execbuffer),
+        EFAULT,
+    );
+    assert!(relocs.intact());
+    assert_eq!(object.relocation_count, 1);
+}
+
+#[test]
+fn hostile_num_engines() {
+    let _lock = TEST_LOCK.lock();
+    // i915_context_param_engines with room for two engines
+    let mut engines = Guarded::<16>::new();
+    let mut param = drm_i915_gem_context_param {
+        ctx_id: 1,
+        size: 16,
+        param: I915_CONTEXT_PARAM_ENGINES,
+        value: engines.ptr(),
+    };
+    hostile(
+        23,
+        DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM,
+        |_, arg| {
+            let param = arg as *mut drm_i915_gem_context_param;
+            unsafe {
+                // The extensions of the engines too, which must not be walked.
+                ptr::write_bytes((*param).value as *mut u8, 0xee, 16);
+                // 1000 engines
+                (*param).size = 8 + 4 * 1000;
+            }
+            Ok(0)
+        },
+        |fd| call(fd, DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM, &mut param),
+        EFAULT,
+    );
+    assert!(engines.intact());
+    assert_eq!(param.size, 16);
+}
+
+// The host keeps rewriting the length and data_ptr of the untrusted item after the driver returned,
+// racing the copy back. The copy must act on the values of the snapshot it validated: either it is
+// rejected, or the item says what the driver wrote, and nothing is written past the trusted buffer.
+#[test]
+fn host_rewrites_reply_after_return() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::{self, JoinHandle};
+
+    let _lock = TEST_LOCK.lock();
+    let secret = [0x5au8; 4096];
+    let secret_ptr = secret.as_ptr() as u64;
+    let stop = Arc::new(AtomicBool::new(false));
+    let racer: Arc<Mutex<Option<JoinHandle<()>>>> = Arc::new(Mutex::new(None));
+    let (stop_racer, spawned) = (stop.clone(), racer.clone());
+    set_driver(Box::new(ScriptedDriver::new().on(
+        DRM_IOCTL_I915_QUERY,
+        move |_, arg| {
+            let query = snapshot::<drm_i915_query>(arg as u64);
+            let item = query.items_ptr as *mut drm_i915_query_item;
+            let data_ptr = unsafe { (*item).data_ptr };
+            unsafe {
+                ptr::write_bytes(data_ptr as *mut u8, 0xee, 8);
+                (*item).length = 8;
+            }
+            let (item, stop) = (item as usize, stop_racer.clone());
+            *spawned.lock() = Some(thread::spawn(move || {
+                let item = item as *mut drm_i915_query_item;
+                while !stop.load(Ordering::Relaxed) {
+                    unsafe {
+                        ptr::write_volatile(&mut (*item).length, 4096);
+                        ptr::write_volatile(&mut (*item).data_ptr, secret_ptr);
+                        ptr::write_volatile(&mut (*item).length, 8);
+                        ptr::write_volatile(&mut (*item).data_ptr, data_ptr);
+                    }
+                }
+            }));
+            Ok(0)
+        },
+    )));
+
+    for _ in 0..200 {
+        let mut data = Guarded::<16>::new();
+        let mut item = drm_i915_query_item {
+            query_id: 1,
+            length: 16,
+            flags: 0,
+            data_ptr: data.ptr(),
+        };
+        let mut query = drm_i915_query {
+            num_items: 1,
+            flags: 0,
+            items_ptr: &mut item as *mut _ as u64,
+        };
+        stop.store(false, Ordering::Relaxed);
+        let ret = call(30, DRM_IOCTL_I915_QUERY, &mut query);
+        // The untrusted copy is only reused once the racer is gone.
+        stop.store(true, Ordering::Relaxed);
+        racer.lock().take().unwrap().join().unwrap();
+
+        assert!(data.intact());
+        assert_eq!(item.data_ptr, data.ptr());
+        match ret {
+            Ok(0) => {
+                assert_eq!(item.length, 8);
+                assert_eq!(
+                    data.buf,
+                    [0xee, 0xee, 0xee, 0xee, 0xee, 0xee, 0xee, 0xee, 0, 0, 0, 0, 0, 0, 0, 0]
+                );
+            }
+            ret => {
+                assert_eq!(ret, Err(EFAULT));
+                assert_eq!(item.length, 16);
+            }
+        }
+    }
+}
+
+#[test]
+fn perf_open_global_sseu() {
+    let _lock = TEST_LOCK.lock();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    set_driver(Box::new(ScriptedDriver::new().on(
+        DRM_IOCTL_I915_PERF_OPEN,
+        move |_, arg| {
+            let param = snapshot::<drm_i915_perf_open_param>(arg as u64);
+            let properties = param.properties_ptr as *const [u64; 2];
+            for i in 0..param.num_properties as usize {
+                let [id, value] = unsafe { *properties.add(i) };
+                if id == DRM_I915_PERF_PROP_GLOBAL_SSEU {
+                    let size = mem::size_of::<drm_i915_gem_context_param_sseu>();
+                    assert!(in_arena(value as *const u8, size));
+                    let sseu = snapshot::<drm_i915_gem_context_param_sseu>(value);
+                    record.lock().push((id, sseu.slice_mask));
+                } else {
+                    record.lock().push((id, value));
+                }
+            }
+            Ok(5)
+        },
+    )));
+    let sseu = drm_i915_gem_context_param_sseu {
+        engine: i915_engine_class_instance {
+            engine_class: 0,
+            engine_instance: 0,
+        },
+        flags: 0,
+        slice_mask: 0x3,
+        subslice_mask: 0xff,
+        min_eus_per_subslice: 8,
+        max_eus_per_subslice: 8,
+        rsvd: 0,
+    };
+    // DRM_I915_PERF_PROP_SAMPLE_OA, then the global sseu
+    let properties: [[u64; 2]; 2] = [
+        [2, 1],
+        [DRM_I915_PERF_PROP_GLOBAL_SSEU, &sseu as *const _ as u64],
+    ];
+    let mut param = drm_i915_perf_open_param {
+        flags: 0,
+        num_properties: properties.len() as u32,
+        properties_ptr: properties.as_ptr() as u64,
+    };
+    let live = crate::memory::live_allocations();
+    assert_eq!(call(15, DRM_IOCTL_I915_PERF_OPEN, &mut param), Ok(5));
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(
+        &seen.lock()[..],
+        &[(2, 1), (DRM_I915_PERF_PROP_GLOBAL_SSEU, 0x3)]
+    );
+    // The properties of the application still point to its sseu.
+    assert_eq!(properties[1][1], &sseu as *const _ as u64);
+}
+
+#[test]
+fn getparam_engines() {
+    let _lock = TEST_LOCK.lock();
+    set_driver(Box::new(ScriptedDriver::new().on(
+        DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM,
+        |_, arg| {
+            let param = snapshot::<drm_i915_gem_context_param>(arg as u64);
+            let value = param.value as *mut u64;
+            // Nothing of the buffer of the application is handed over.
+            assert_eq!(unsafe { *value.add(1) }, 0);
+            unsafe {
+                *value = 0;
+                // Two engines: (0, 0) and (1, 0)
+                *value.add(1) = 1 << 32;
+            }
+            Ok(0)
+        },
+    )));
+    // Uninitialized: the extensions must not be walked.
+    let mut engines: [u64; 2] = [0xeeee_eeee_eeee_eeee; 2];
+    let mut param = drm_i915_gem_context_param {
+        ctx_id: 1,
+        size: mem::size_of_val(&engines) as u32,
+        param: I915_CONTEXT_PARAM_ENGINES,
+        value: engines.as_mut_ptr() as u64,
+    };
+    let live = crate::memory::live_allocations();
+    assert_eq!(
+        call(16, DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM, &mut param),
+        Ok(0)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(param.size, 16);
+    assert_eq!(param.value, engines.as_ptr() as u64);
+    assert_eq!(engines, [0, 1 << 32]);
+}
+
+#[test]
+fn getparam_sseu() {
+    let _lock = TEST_LOCK.lock();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+    set_driver(Box::new(ScriptedDriver::new().on(
+        DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM,
+        move |_, arg| {
+            let param = snapshot::<drm_i915_gem_context_param>(arg as u64);
+            let sseu = unsafe { &mut *(param.value as *mut drm_i915_gem_context_param_sseu) };
+            // Like get_sseu(), the engine and flags are read before the reply is written.
+            record.lock().push((
+                sseu.engine.engine_class,
+                sseu.engine.engine_instance,
+                sseu.flags,
+            ));
+            sseu.slice_mask = 0x1;
+            sseu.subslice_mask = 0xf;
+            Ok(0)
+        },
+    )));
+    let mut sseu = drm_i915_gem_context_param_sseu {
+        engine: i915_engine_class_instance {
+            engine_class: 2,
+            engine_instance: 1,
+        },
+        // I915_CONTEXT_SSEU_FLAG_ENGINE_INDEX
+        flags: 1,
+        slice_mask: 0,
+        subslice_mask: 0,
+        min_eus_per_subslice: 0,
+        max_eus_per_subslice: 0,
+        rsvd: 0,
+    };
+    let mut param = drm_i915_gem_context_param {
+        ctx_id: 1,
+        size: mem::size_of_val(&sseu) as u32,
+        param: I915_CONTEXT_PARAM_SSEU,
+        value: &mut sseu as *mut _ as u64,
+    };
+    let live = crate::memory::live_allocations();
+    assert_eq!(
+        call(25, DRM_IOCTL_I915_GEM_CONTEXT_GETPARAM, &mut param),
+        Ok(0)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(&seen.lock()[..], &[(2, 1, 1)]);
+    assert_eq!((sseu.slice_mask, sseu.subslice_mask), (0x1, 0xf));
+    assert_eq!(
+        (sseu.engine.engine_class, sseu.engine.engine_instance),
+        (2, 1)
+    );
+}
+
+// DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL only reads the points: whatever the host writes over them in
+// the untrusted copy must not reach the application, while DRM_IOCTL_SYNCOBJ_QUERY copies them back.
+#[test]
+fn hostile_timeline_signal_points() {
+    let _lock = TEST_LOCK.lock();
+    let overwrite = |_, arg: *mut u8| {
+        let array = snapshot::<drm_syncobj_timeline_array>(arg as u64);
+        unsafe { ptr::write_bytes(array.points as *mut u8, 0xee, 16) };
+        Ok(0)
+    };
+    set_driver(Box::new(
+        ScriptedDriver::new()
+            .on(DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL, overwrite)
+            .on(DRM_IOCTL_SYNCOBJ_QUERY, overwrite),
+    ));
+    let handles = [1u32, 2];
+    let mut points = [7u64, 8];
+    let mut array = drm_syncobj_timeline_array {
+        handles: handles.as_ptr() as u64,
+        points: points.as_mut_ptr() as u64,
+        count_handles: 2,
+        flags: 0,
+    };
+    let live = crate::memory::live_allocations();
+    assert_eq!(
+        call(26, DRM_IOCTL_SYNCOBJ_TIMELINE_SIGNAL, &mut array),
+        Ok(0)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(points, [7, 8]);
+    assert_eq!(call(26, DRM_IOCTL_SYNCOBJ_QUERY, &mut array), Ok(0));
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(points, [0xeeee_eeee_eeee_eeee; 2]);
+}
+
+// No extension is defined for these ioctls: a non-zero extensions is rejected before the driver.
+#[test]
+fn extensions_mbz() {
+    let _lock = TEST_LOCK.lock();
+    set_driver(Box::new(
+        ScriptedDriver::new()
+            .on(PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE, |_, _| {
+                panic!("wait_user_fence reached the driver")
+            })
+            .on(PRELIM_DRM_IOCTL_I915_UUID_REGISTER, |_, _| {
+                panic!("uuid_register reached the driver")
+            }),
+    ));
+    let extension = zeroed::<i915_user_extension>();
+    let fence = alloc_untrusted(mem::size_of::<u64>(), mem::size_of::<u64>()) as u64;
+    let mut wait = zeroed::<prelim_drm_i915_gem_wait_user_fence>();
+    wait.extensions = &extension as *const _ as u64;
+    wait.addr = fence;
+    let payload = [0u8; 16];
+    let mut uuid = zeroed::<prelim_drm_i915_uuid_control>();
+    uuid.ptr = payload.as_ptr() as u64;
+    uuid.size = payload.len() as u64;
+    uuid.extensions = &extension as *const _ as u64;
+    let live = crate::memory::live_allocations();
+    assert_eq!(
+        call(27, PRELIM_DRM_IOCTL_I915_GEM_WAIT_USER_FENCE, &mut wait),
+        Err(EINVAL)
+    );
+    assert_eq!(
+        call(27, PRELIM_DRM_IOCTL_I915_UUID_REGISTER, &mut uuid),
+        Err(EINVAL)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+}
+
+#[test]
+fn mmap_inside_object() {
+    let _lock = TEST_LOCK.lock();
+    let fd = 17;
+    set_driver(Box::new(
+        ScriptedDriver::new()
+            .on(DRM_IOCTL_I915_GEM_CREATE, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_create)).handle = 7 };
+                Ok(0)
+            })
+            .on(DRM_IOCTL_I915_GEM_MMAP_OFFSET, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_mmap_offset)).offset = 0x100000 };
+                Ok(0)
+            }),
+    ));
+    assert_eq!(create(fd, 8192), 7);
+    let mut mmap_offset = drm_i915_gem_mmap_offset {
+        handle: 7,
+        pad: 0,
+        offset: 0,
+        // I915_MMAP_OFFSET_WB
+        flags: 2,
+        extensions: 0,
+    };
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_MMAP_OFFSET, &mut mmap_offset),
+        Ok(0)
+    );
+    assert_eq!(mmap_offset.offset, 0x100000);
+
+    // PROT_READ | PROT_WRITE, MAP_SHARED
+    let map = |len: usize, offset: i64| pxp_mmap(ptr::null_mut(), len, 3, 1, fd, offset);
+    for (len, offset) in [(8192, 0x100000), (4096, 0x101000)] {
+        let ptr = map(len, offset);
+        assert!(in_arena(ptr, len));
+        assert_eq!(pxp_munmap(ptr, len), 0);
+    }
+    for (len, offset) in [(8192, 0x101000), (0x3000, 0x100000), (4096, 0x102000)] {
+        assert_eq!(map(len, offset), !0 as *mut u8);
+        assert_eq!(errno(), EINVAL);
+    }
+
+    // No extension is defined.
+    let extension = zeroed::<i915_user_extension>();
+    mmap_offset.extensions = &extension as *const _ as u64;
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_MMAP_OFFSET, &mut mmap_offset),
+        Err(EINVAL)
+    );
+}
+
+// A non-zero extensions fails like the kernel's i915_gem_mmap_offset_ioctl(), without reaching the
+// driver.
+#[test]
+fn mmap_offset_extensions() {
+    let _lock = TEST_LOCK.lock();
+    set_driver(Box::new(
+        ScriptedDriver::new().on(DRM_IOCTL_I915_GEM_MMAP_OFFSET, |_, _| {
+            panic!("mmap_offset reached the driver")
+        }),
+    ));
+    let extension = zeroed::<i915_user_extension>();
+    let mut mmap_offset = drm_i915_gem_mmap_offset {
+        handle: 7,
+        pad: 0,
+        offset: 0,
+        // I915_MMAP_OFFSET_WB
+        flags: 2,
+        extensions: &extension as *const _ as u64,
+    };
+    let live = crate::memory::live_allocations();
+    assert_eq!(
+        call(28, DRM_IOCTL_I915_GEM_MMAP_OFFSET, &mut mmap_offset),
+        Err(EINVAL)
+    );
+    assert_eq!(crate::memory::live_allocations(), live);
+    assert_eq!(mmap_offset.offset, 0);
+}
+
+// The mock transport, with a host munmap() that fails for the first mapping it's given.
+struct FailingMunmap {
+    unmapped: Mutex<Vec<usize>>,
+}
+
+impl Transport for FailingMunmap {
+    fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+        MockTransport.ioctl(fd, request, arg)
+    }
+    fn alloc(&self, size: usize, align: usize) -> Result<*mut u8, Error> {
+        MockTransport.alloc(size, align)
+    }
+    fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+        MockTransport.free(ptr)
+    }
+    fn mmap(
+        &self,
+        addr: *mut u8,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> Result<*mut u8, Error> {
+        MockTransport.mmap(addr, len, prot, flags, fd, offset)
+    }
+    fn munmap(&self, addr: *mut u8, _len: usize) -> Result<(), Error> {
+        let mut unmapped = self.unmapped.lock();
+        unmapped.push(addr as usize);
+        if unmapped.len() == 1 {
+            return Err(Error::HostMmap(EINVAL));
+        }
+        Ok(())
+    }
+    fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool {
+        MockTransport.is_outside_enclave(ptr, size)
+    }
+}
+
+#[test]
+fn gem_close_unmaps_every_mapping() {
+    static FAILING: FailingMunmap = FailingMunmap {
+        unmapped: Mutex::new(Vec::new()),
+    };
+    let _lock = TEST_LOCK.lock();
+    let fd = 18;
+    set_driver(Box::new(
+        ScriptedDriver::new()
+            .on(DRM_IOCTL_I915_GEM_CREATE, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_create)).handle = 3 };
+                Ok(0)
+            })
+            .on(DRM_IOCTL_I915_GEM_MMAP_OFFSET, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_mmap_offset)).offset = 0x200000 };
+                Ok(0)
+            })
+            .on(DRM_IOCTL_GEM_CLOSE, |_, _| Ok(0)),
+    ));
+    let handle = create(fd, 4096);
+    let mut mmap_offset = drm_i915_gem_mmap_offset {
+        handle,
+        pad: 0,
+        offset: 0,
+        // I915_MMAP_OFFSET_WC
+        flags: 1,
+        extensions: 0,
+    };
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_MMAP_OFFSET, &mut mmap_offset),
+        Ok(0)
+    );
+    let maps: Vec<usize> = (0..2)
+        .map(|_| pxp_mmap(ptr::null_mut(), 4096, 3, 1, fd, 0x200000) as usize)
+        .collect();
+
+    set_transport(&FAILING);
+    let mut close = drm_gem_close_t { handle, pad: 0 };
+    let ret = call(fd, DRM_IOCTL_GEM_CLOSE, &mut close);
+    set_transport(&MockTransport);
+    // The handle is closed by the kernel, whatever became of the mappings.
+    assert_eq!(ret, Ok(0));
+    assert_eq!(*FAILING.unmapped.lock(), maps);
+    assert_eq!(pxp_munmap(maps[1] as *mut u8, 4096), -1);
+    assert!(crate::gem::lookup_object(fd, handle).is_none());
+}
+
+#[test]
+fn forget_fd_drops_stale_records() {
+    let _lock = TEST_LOCK.lock();
+    let fd = 19;
+    set_driver(Box::new(
+        ScriptedDriver::new()
+            .on(DRM_IOCTL_I915_GEM_CREATE, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_create)).handle = 1 };
+                Ok(0)
+            })
+            .on(DRM_IOCTL_I915_GEM_MMAP_OFFSET, |_, arg| {
+                unsafe { (*(arg as *mut drm_i915_gem_mmap_offset)).offset = 0x300000 };
+                Ok(0)
+            })
+            .on(DRM_IOCTL_GEM_CLOSE, |_, _| Ok(0)),
+    ));
+    create(fd, 4096);
+    let mut mmap_offset = drm_i915_gem_mmap_offset {
+        handle: 1,
+        pad: 0,
+        offset: 0,
+        // I915_MMAP_OFFSET_WB
+        flags: 2,
+        extensions: 0,
+    };
+    assert_eq!(
+        call(fd, DRM_IOCTL_I915_GEM_MMAP_OFFSET, &mut mmap_offset),
+        Ok(0)
+    );
+    let map = pxp_mmap(ptr::null_mut(), 4096, 3, 1, fd, 0x300000);
+    assert!(in_arena(map, 4096));
+
+    crate::gem::pxp_forget_fd(fd);
+    assert!(crate::gem::live_objects(fd).is_empty());
+    assert!(crate::mmap::lookup_offset(fd, 0x300000).is_none());
+
+    // The number is reused: the new handle 1 is a new object, whose size is recorded.
+    create(fd, 8192);
+    assert_eq!(crate::gem::lookup_object(fd, 1).unwrap().size, Some(8192));
+    let mut close = drm_gem_close_t { handle: 1, pad: 0 };
+    assert_eq!(call(fd, DRM_IOCTL_GEM_CLOSE, &mut close), Ok(0));
+    // The mapping of the old fd wasn't unmapped by it, and can still be.
+    assert_eq!(pxp_munmap(map, 4096), 0);
+}
+
+#[test]
+fn record_then_replay() {
+    let _lock = TEST_LOCK.lock();
+    let fd = 24;
+    let fake = fake_i915();
+    fake.state().query_items.insert(1, vec![3; 24]);
+    let data = vec![0x5au8; 0x10000];
+
+    crate::trace::start_recording();
+    let handle = create(fd, data.len() as u64);
+    let mut pwrite = drm_i915_gem_pwrite {
+        handle,
+        pad: 0,
+        offset: 0,
+        size: data.len() as u64,
+        data_ptr: data.as_ptr() as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_GEM_PWRITE, &mut pwrite), Ok(0));
+    let mut item_data = [0u8; 24];
+    let mut item = drm_i915_query_item {
+        query_id: 1,
+        length: item_data.len() as i32,
+        flags: 0,
+        data_ptr: item_data.as_mut_ptr() as u64,
+    };
+    let mut query = drm_i915_query {
+        num_items: 1,
+        flags: 0,
+        items_ptr: &mut item as *mut _ as u64,
+    };
+    assert_eq!(call(fd, DRM_IOCTL_I915_QUERY, &mut query), Ok(0));
+    let trace = crate::trace::stop_recording();
+    // The data of PWRITE is only read by the kernel, so it is recorded once.
+    assert!(trace.len() < data.len() + 0x400, "{}", trace.len());
+
+    // Against a fresh driver, the same handle is returned and the data lands in the new object.
+    let replayed = fake_i915();
+    replayed.state().query_items.insert(1, vec![3; 24]);
+    let mark = alloc_untrusted(1, 1);
+    MockTransport.free(mark).unwrap();
+    let mismatches = crate::trace::replay(&trace, &MockTransport, &|fd| fd).unwrap();
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+    assert_eq!(replayed.state().objects[&(fd, handle)].contents, data);
+    assert!(replayed.state().violations.is_empty());
+    // The blocks of the replay are given back to the arena.
+    assert_eq!(alloc_untrusted(1, 1), mark);
+}
@@ -9,12 +9,34 @@ extern crate alloc;
 extern crate log;
 
 mod buddy_alloc;
+mod error;
+mod gem;
 mod i915;
 mod memory;
+mod mmap;
+mod strace;
+mod trace;
+mod transport;
 cfg_if::cfg_if! {
-    if #[cfg(not(feature = "occlum"))] {
+    if #[cfg(feature = "mock")] {
+        mod fake_i915;
+        mod mock;
+        pub use fake_i915::{
+            FakeExecbuffer, FakeI915, FakeObject, FakeSession, I915State, SessionState,
+        };
+        pub use mock::{
+            alloc_untrusted, in_arena, set_driver, FakeDriver, IoctlHandler, MockTransport,
+            ScriptedDriver,
+        };
+    } else if #[cfg(not(feature = "occlum"))] {
         mod sgx_no_std;
     }
 }
 
+pub use error::Error;
+pub use gem::{live_objects, lookup_object, pxp_forget_fd, GemObject, MemoryRegion, Origin};
 pub use i915::pxp_ioctl;
+pub use mmap::{lookup_offset, pxp_mmap, pxp_munmap, MmapOffset, MmapType};
+pub use strace::{set_trace_filter, trace_all, IoctlFamily};
+pub use trace::{replay, start_recording, stop_recording, Mismatch};
+pub use transport::{set_transport, Transport};
@@ -1,55 +1,36 @@
 use crate::buddy_alloc::BuddyAllocatorManager;
+use crate::error::Error;
+use crate::transport::transport;
 use alloc::alloc::Layout;
-use alloc::string::String;
-use cfg_if;
+use alloc::collections::BTreeMap;
 use core::ffi::c_void;
 //use ctor::dtor;
-use sgx_types::sgx_status_t;
-cfg_if::cfg_if! {
-    if #[cfg(feature = "occlum")] {
-        use sgx_trts;
-    }
-}
+use spin::Mutex;
 
 pub const PTR_NULL: u64 = 0;
 static MANAGER: BuddyAllocatorManager = BuddyAllocatorManager::new();
+// The live allocations: [ address -> size ]. The arguments of free() may have been read back from
+// untrusted memory, so only a block really handed out by alloc() is given back to the MANAGER.
+static ALLOCATIONS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
 
-fn untrusted_mem_alloc(size: usize) -> Result<(), String> {
+fn untrusted_mem_alloc(size: usize) -> Result<(), Error> {
     let chunk = if size <= 1024 {
         1024
     } else {
-        size.next_power_of_two()
+        size.checked_next_power_of_two()
+            .ok_or(Error::UntrustedAlloc(size))?
     }
     .checked_mul(2)
-    .unwrap();
+    .ok_or(Error::UntrustedAlloc(size))?;
 
     let layout = Layout::from_size_align(chunk, 1)
-        .unwrap()
-        .align_to(core::mem::size_of::<*const c_void>())
-        .unwrap();
+        .and_then(|layout| layout.align_to(core::mem::size_of::<*const c_void>()))
+        .map_err(|_| Error::UntrustedAlloc(size))?;
 
-    let mut mem_ptr: *mut c_void = core::ptr::null_mut();
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "occlum")] {
-            info!(
-                "pxp-rs:v1: allocate untrusted memory: [ 0x{:x} ]",
-                &chunk
-            );
-            let sgx_status = unsafe {
-                occlum_ocall_posix_memalign(&mut mem_ptr as *mut _, layout.align(), layout.size())
-            };
-            assert!(sgx_status == sgx_status_t::SGX_SUCCESS);
-            assert!(sgx_trts::trts::rsgx_raw_is_outside_enclave(
-                mem_ptr as *const u8,
-                layout.size()
-            ));
-        } else {
-            let sgx_status = unsafe {
-                u_malloc(&mut mem_ptr as *mut _, layout.size())
-            };
-            assert!(sgx_status == sgx_status_t::SGX_SUCCESS);
-            assert!(unsafe { sgx_is_outside_enclave(mem_ptr, layout.size()) } != 0);
-        }
+    info!("pxp-rs:v1: allocate untrusted memory: [ 0x{:x} ]", &chunk);
+    let mem_ptr = transport().alloc(layout.size(), layout.align())?;
+    if mem_ptr.is_null() || !transport().is_outside_enclave(mem_ptr, layout.size()) {
+        return Err(Error::UntrustedAlloc(chunk));
     }
     unsafe {
         MANAGER.init(mem_ptr as usize, chunk, 16);
@@ -64,61 +45,65 @@ fn untrusted_mem_alloc(size: usize) -> Result<(), String> {
 fn untrusted_mem_free() {
     for range in MANAGER.fetch_memory_ranges().unwrap() {
         info!("Free untrusted memory: {:x}", range);
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "occlum")] {
-                let sgx_status = unsafe { occlum_ocall_free(range as *mut c_void) };
-                assert!(sgx_status == sgx_status_t::SGX_SUCCESS);
-            } else {
-                let sgx_status = unsafe { u_free(range as *mut c_void) };
-                assert!(sgx_status == sgx_status_t::SGX_SUCCESS);
-            }
-        }
+        transport().free(range as *mut u8).unwrap();
     }
 }*/
 
-pub fn alloc(size: usize) -> Result<*mut u8, String> {
+pub fn alloc(size: usize) -> Result<*mut u8, Error> {
     let ptr = if size > 0 {
         //info!("alloc: size:{:?}", size);
-        let layout = Layout::from_size_align(size, 1).unwrap();
+        let layout = Layout::from_size_align(size, 1).map_err(|_| Error::UntrustedAlloc(size))?;
         let ptr = MANAGER.alloc(layout);
         match ptr {
-            Ok(ptr) => ptr.as_ptr() as *mut u8,
+            Ok(ptr) => ptr.as_ptr(),
             Err(_) => {
-                untrusted_mem_alloc(size.clone()).unwrap();
-                MANAGER.alloc(layout).unwrap().as_ptr() as *mut u8
+                untrusted_mem_alloc(size)?;
+                MANAGER
+                    .alloc(layout)
+                    .map_err(|_| Error::UntrustedAlloc(size))?
+                    .as_ptr()
             }
         }
     } else {
         PTR_NULL as *mut u8
     };
+    if size > 0 {
+        ALLOCATIONS.lock().insert(ptr as usize, size);
+    }
     Ok(ptr)
 }
 
-pub fn free(ptr: *mut u8, size: usize) -> Result<(), String> {
-    if ptr as u64 != PTR_NULL {
-        //info!("free: size:{:?}", size);
-        let layout = Layout::from_size_align(size, 1).unwrap();
-        MANAGER.dealloc(core::ptr::NonNull::new(ptr).unwrap(), layout);
+pub fn free(ptr: *mut u8, _size: usize) -> Result<(), Error> {
+    if let Some(ptr) = core::ptr::NonNull::new(ptr) {
+        //info!("free: size:{:?}", _size);
+        // The size may have been changed since alloc(), by the host or even by the kernel (e.g. the
+        // length of a query item), so the recorded one is used.
+        let size = ALLOCATIONS
+            .lock()
+            .remove(&(ptr.as_ptr() as usize))
+            .ok_or(Error::UntrustedValue(format!(
+                "free: 0x{:x} is not allocated",
+                ptr.as_ptr() as usize
+            )))?;
+        let layout = Layout::from_size_align(size, 1)
+            .map_err(|_| Error::InvalidArgument(format!("bad free size: {:?}", size)))?;
+        MANAGER.dealloc(ptr, layout);
     }
     Ok(())
 }
 
-// External functions
-cfg_if::cfg_if! {
-    if #[cfg(feature = "occlum")] {
-        extern "C" {
-            fn occlum_ocall_posix_memalign(
-                ptr: *mut *mut c_void,
-                align: usize, // must be power of two and a multiple of sizeof(void*)
-                size: usize,
-            ) -> sgx_status_t;
-            //fn occlum_ocall_free(ptr: *mut c_void) -> sgx_status_t;
-        }
-    } else {
-        extern "C" {
-            fn u_malloc(ptr: *mut *mut c_void, size: usize) -> sgx_status_t;
-            fn u_free(ptr: *mut c_void) -> sgx_status_t;
-            fn sgx_is_outside_enclave(ptr: *mut c_void, size: usize) -> i32;
-        }
-    }
+// The live allocation containing addr, as (address, size).
+pub fn allocation_of(addr: usize) -> Option<(usize, usize)> {
+    ALLOCATIONS
+        .lock()
+        .range(..=addr)
+        .next_back()
+        .map(|(start, size)| (*start, *size))
+        .filter(|(start, size)| addr < start + size)
+}
+
+// The number of live allocations, to check that the untrusted memory of a call is given back.
+#[cfg(test)]
+pub fn live_allocations() -> usize {
+    ALLOCATIONS.lock().len()
 }
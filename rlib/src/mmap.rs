@@ -0,0 +1,216 @@
+use crate::error::{errno, set_errno, Error};
+use crate::gem::lookup_object;
+use crate::transport::transport;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+// The mapping types of DRM_IOCTL_I915_GEM_MMAP_OFFSET: I915_MMAP_OFFSET_*
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MmapType {
+    GTT,
+    WC,
+    WB,
+    UC,
+    FIXED,
+}
+
+impl MmapType {
+    pub fn from_flags(flags: u64) -> Option<MmapType> {
+        match flags {
+            0 => Some(MmapType::GTT),
+            1 => Some(MmapType::WC),
+            2 => Some(MmapType::WB),
+            3 => Some(MmapType::UC),
+            4 => Some(MmapType::FIXED),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct MmapOffset {
+    pub handle: u32,
+    pub mmap_type: MmapType,
+}
+
+// The fake offsets returned by the kernel: [ (fd, offset) -> (handle, type) ]. An offset is only
+// meaningful for the fd it was returned on.
+static OFFSETS: Mutex<BTreeMap<(i32, u64), MmapOffset>> = Mutex::new(BTreeMap::new());
+// The mappings of one GEM object: [ (address, length) ].
+type Ranges = Vec<(usize, usize)>;
+// The live mappings of GEM objects: [ (fd, handle) -> ranges ].
+static MAPPINGS: Mutex<BTreeMap<(i32, u32), Ranges>> = Mutex::new(BTreeMap::new());
+
+pub fn insert_offset(fd: i32, offset: u64, handle: u32, mmap_type: MmapType) {
+    OFFSETS
+        .lock()
+        .insert((fd, offset), MmapOffset { handle, mmap_type });
+}
+
+pub fn lookup_offset(fd: i32, offset: u64) -> Option<MmapOffset> {
+    OFFSETS.lock().get(&(fd, offset)).copied()
+}
+
+// The fake offset at or below `offset`, and how far `offset` is past it. Like the vm_pgoff of a
+// mapping, an offset into the object maps it from there.
+fn offset_at(fd: i32, offset: u64) -> Option<(MmapOffset, u64)> {
+    OFFSETS
+        .lock()
+        .range((fd, 0)..=(fd, offset))
+        .next_back()
+        .map(|(&(_, start), mmap_offset)| (*mmap_offset, offset - start))
+}
+
+// Record a mapping made by the host, e.g. by DRM_IOCTL_I915_GEM_MMAP.
+pub fn insert_mapping(fd: i32, handle: u32, addr: usize, len: usize) {
+    MAPPINGS
+        .lock()
+        .entry((fd, handle))
+        .or_default()
+        .push((addr, len));
+}
+
+// Map a GEM object through a fake offset returned by DRM_IOCTL_I915_GEM_MMAP_OFFSET. The mapping is
+// untrusted memory.
+pub fn mmap(
+    addr: *mut u8,
+    len: usize,
+    prot: i32,
+    flags: i32,
+    fd: i32,
+    offset: i64,
+) -> Result<*mut u8, Error> {
+    let (mmap_offset, start) = offset_at(fd, offset as u64).ok_or(Error::InvalidArgument(format!(
+        "mmap: offset 0x{:x} isn't returned by GEM_MMAP_OFFSET",
+        offset
+    )))?;
+    let handle = mmap_offset.handle;
+    if len == 0 {
+        return Err(Error::InvalidArgument(String::from("mmap: length is 0")));
+    }
+    // The range must be inside the object. The size of an imported dma-buf is unknown, so it can
+    // only be mapped from its start.
+    let object = lookup_object(fd, handle).ok_or(Error::InvalidArgument(format!(
+        "mmap: handle {} isn't a live GEM object",
+        handle
+    )))?;
+    let end = start.checked_add(len as u64);
+    match (object.size, end) {
+        (Some(size), Some(end)) if end <= size => (),
+        (None, Some(_)) if start == 0 => (),
+        _ => {
+            return Err(Error::InvalidArgument(format!(
+                "mmap: [ 0x{:x}, 0x{:x} ] is past the end of handle {}",
+                start, len, handle
+            )))
+        }
+    }
+    if !addr.is_null() && !transport().is_outside_enclave(addr, len) {
+        return Err(Error::InvalidArgument(format!(
+            "mmap: can't map at TRUSTED address {:?}",
+            addr
+        )));
+    }
+    let ptr = transport().mmap(addr, len, prot, flags, fd, offset)?;
+    if ptr == MAP_FAILED {
+        return Err(Error::HostMmap(errno()));
+    }
+    if ptr.is_null() || !transport().is_outside_enclave(ptr, len) {
+        return Err(Error::UntrustedValue(format!(
+            "mmap: {:?} isn't outside enclave",
+            ptr
+        )));
+    }
+    insert_mapping(fd, handle, ptr as usize, len);
+    Ok(ptr)
+}
+
+fn host_munmap(addr: usize, len: usize) -> Result<(), Error> {
+    transport().munmap(addr as *mut u8, len)
+}
+
+// Unmap a whole mapping made by mmap(). Only tracked mappings are given to the host.
+pub fn munmap(addr: *mut u8, len: usize) -> Result<(), Error> {
+    let mut mappings = MAPPINGS.lock();
+    let key = mappings
+        .iter()
+        .find(|(_, ranges)| ranges.contains(&(addr as usize, len)))
+        .map(|(key, _)| *key)
+        .ok_or(Error::InvalidArgument(format!(
+            "munmap: [ {:?}, 0x{:x} ] isn't mapped",
+            addr, len
+        )))?;
+    host_munmap(addr as usize, len)?;
+    if let Some(ranges) = mappings.get_mut(&key) {
+        ranges.retain(|range| *range != (addr as usize, len));
+        if ranges.is_empty() {
+            mappings.remove(&key);
+        }
+    }
+    Ok(())
+}
+
+// The GEM object is closed: unmap its mappings and forget its offsets, which are released by the
+// kernel. The handle is gone already, so a mapping that fails to unmap is only logged and the others
+// are still unmapped.
+pub fn close_handle(fd: i32, handle: u32) {
+    OFFSETS
+        .lock()
+        .retain(|&(f, _), offset| f != fd || offset.handle != handle);
+    let ranges = MAPPINGS.lock().remove(&(fd, handle)).unwrap_or_default();
+    for (addr, len) in ranges {
+        if let Err(e) = host_munmap(addr, len) {
+            error!(
+                "PXP close: munmap [ 0x{:x}, 0x{:x} ] of handle {} failed: {}",
+                addr, len, handle, e
+            );
+        }
+    }
+}
+
+// The fd is closed: its offsets are gone. Its mappings stay until munmap(), but no GEM_CLOSE can reach
+// them anymore, so they are kept under the fd -1, which no fd is.
+pub fn forget_fd(fd: i32) {
+    OFFSETS.lock().retain(|&(f, _), _| f != fd);
+    let mut mappings = MAPPINGS.lock();
+    let handles: Vec<u32> = mappings
+        .range((fd, 0)..=(fd, u32::MAX))
+        .map(|(&(_, handle), _)| handle)
+        .collect();
+    for handle in handles {
+        if let Some(ranges) = mappings.remove(&(fd, handle)) {
+            mappings.entry((-1, handle)).or_default().extend(ranges);
+        }
+    }
+}
+
+// Returns the address of the mapping on success. On failure returns MAP_FAILED and sets errno, like
+// mmap(2).
+#[no_mangle]
+pub fn pxp_mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8 {
+    match mmap(addr, len, prot, flags, fd, offset) {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            error!("PXP mmap: offset 0x{:x} failed: {}", offset, e);
+            set_errno(e.errno());
+            MAP_FAILED
+        }
+    }
+}
+
+// Returns 0 on success. On failure returns -1 and sets errno, like munmap(2).
+#[no_mangle]
+pub fn pxp_munmap(addr: *mut u8, len: usize) -> i32 {
+    match munmap(addr, len) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("PXP munmap: {:?} failed: {}", addr, e);
+            set_errno(e.errno());
+            -1
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::error::{set_errno, Error, EFAULT, ENOMEM, ENOTTY};
+use crate::transport::{timespec, Transport, CLOCK_MONOTONIC};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::cell::UnsafeCell;
+use spin::Mutex;
+
+// An in-process stand-in for the host, to run the marshalling code outside an enclave: the
+// "untrusted" memory is a separate arena, and the ioctls go to a fake driver instead of the kernel.
+// Everything not in the arena is treated as enclave memory.
+
+const PAGE_SIZE: usize = 4096;
+const ARENA_SIZE: usize = 16 << 20;
+
+#[repr(C, align(4096))]
+struct Arena(UnsafeCell<[u8; ARENA_SIZE]>);
+
+// The arena is only handed out in disjoint blocks, under the lock of BLOCKS.
+unsafe impl Sync for Arena {}
+
+static ARENA: Arena = Arena(UnsafeCell::new([0; ARENA_SIZE]));
+// The blocks handed out: [ offset -> end ]. A block is given back by MockTransport::free(), the arena
+// is reused from the end of the last block still in use.
+static BLOCKS: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+fn arena_base() -> usize {
+    ARENA.0.get() as usize
+}
+
+// Allocate `size` bytes of "untrusted" memory, e.g. for the buffers of GEM_USERPTR or the pointers
+// handed to pxp_ioctl() which are expected outside enclave. Returns null if the arena is exhausted.
+pub fn alloc_untrusted(size: usize, align: usize) -> *mut u8 {
+    let mut blocks = BLOCKS.lock();
+    let next = blocks.values().next_back().copied().unwrap_or(0);
+    let align = align.max(1).next_power_of_two();
+    let start = match (arena_base() + next).checked_add(align - 1) {
+        Some(end) => (end & !(align - 1)) - arena_base(),
+        None => return core::ptr::null_mut(),
+    };
+    match start.checked_add(size) {
+        Some(end) if end <= ARENA_SIZE => {
+            blocks.insert(start, end);
+            (arena_base() + start) as *mut u8
+        }
+        _ => core::ptr::null_mut(),
+    }
+}
+
+// Whether [ ptr, ptr + size ) is in the arena, i.e. "outside enclave".
+pub fn in_arena(ptr: *const u8, size: usize) -> bool {
+    let ptr = ptr as usize;
+    match ptr.checked_add(size) {
+        Some(end) => ptr >= arena_base() && end <= arena_base() + ARENA_SIZE,
+        None => false,
+    }
+}
+
+// The kernel side of the mock. `arg` is the untrusted copy of the ioctl argument, in the arena.
+// Returns the result of ioctl(2), or the errno to fail with.
+pub trait FakeDriver: Send {
+    fn ioctl(&mut self, fd: i32, request: u32, arg: *mut u8) -> Result<i32, i32>;
+}
+
+pub type IoctlHandler = Box<dyn FnMut(i32, *mut u8) -> Result<i32, i32> + Send>;
+
+// A driver answering each command with a handler set by the test. Unknown commands fail with ENOTTY,
+// like an unknown ioctl of a real driver.
+pub struct ScriptedDriver {
+    handlers: BTreeMap<u32, IoctlHandler>,
+}
+
+impl ScriptedDriver {
+    pub fn new() -> ScriptedDriver {
+        ScriptedDriver {
+            handlers: BTreeMap::new(),
+        }
+    }
+
+    pub fn on<F>(mut self, request: u32, handler: F) -> ScriptedDriver
+    where
+        F: FnMut(i32, *mut u8) -> Result<i32, i32> + Send + 'static,
+    {
+        self.handlers.insert(request, Box::new(handler));
+        self
+    }
+}
+
+impl Default for ScriptedDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeDriver for ScriptedDriver {
+    fn ioctl(&mut self, fd: i32, request: u32, arg: *mut u8) -> Result<i32, i32> {
+        match self.handlers.get_mut(&request) {
+            Some(handler) => handler(fd, arg),
+            None => Err(ENOTTY),
+        }
+    }
+}
+
+static DRIVER: Mutex<Option<Box<dyn FakeDriver>>> = Mutex::new(None);
+
+// The driver, the transport and the registries of the crate are global, so the tests take turns.
+#[cfg(test)]
+pub(crate) static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+// Install the driver the ioctls go to, replacing the previous one. It is shared by all threads.
+pub fn set_driver(driver: Box<dyn FakeDriver>) {
+    *DRIVER.lock() = Some(driver);
+}
+
+pub struct MockTransport;
+
+impl Transport for MockTransport {
+    fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+        // The kernel can't read enclave memory: fail like it does for a bad address.
+        let ret = if arg != 0 && !in_arena(arg as *const u8, 1) {
+            Err(EFAULT)
+        } else {
+            match DRIVER.lock().as_mut() {
+                Some(driver) => driver.ioctl(fd, request, arg as *mut u8),
+                None => Err(ENOTTY),
+            }
+        };
+        match ret {
+            Ok(ret) => {
+                set_errno(0);
+                Ok(ret)
+            }
+            Err(errno) => {
+                set_errno(errno);
+                Err(Error::HostIoctl(errno))
+            }
+        }
+    }
+    fn alloc(&self, size: usize, align: usize) -> Result<*mut u8, Error> {
+        Ok(alloc_untrusted(size, align))
+    }
+    fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+        if ptr.is_null() {
+            return Ok(());
+        }
+        let offset = (ptr as usize).wrapping_sub(arena_base());
+        match BLOCKS.lock().remove(&offset) {
+            Some(_) => Ok(()),
+            None => Err(Error::InvalidArgument(format!(
+                "free: 0x{:x} is not allocated",
+                ptr as usize
+            ))),
+        }
+    }
+    fn mmap(
+        &self,
+        _addr: *mut u8,
+        len: usize,
+        _prot: i32,
+        _flags: i32,
+        _fd: i32,
+        _offset: i64,
+    ) -> Result<*mut u8, Error> {
+        // The address is only a hint, the mapping is carved from the arena.
+        let ptr = alloc_untrusted(len, PAGE_SIZE);
+        if ptr.is_null() {
+            set_errno(ENOMEM);
+            return Ok(!0 as *mut u8);
+        }
+        Ok(ptr)
+    }
+    fn munmap(&self, _addr: *mut u8, _len: usize) -> Result<(), Error> {
+        Ok(())
+    }
+    fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool {
+        in_arena(ptr, size)
+    }
+    fn clock(&self) -> Option<u64> {
+        let mut ts = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts) } != 0 {
+            return None;
+        }
+        ts.nanoseconds()
+    }
+}
+
+extern "C" {
+    // Provided by the host's libc
+    fn clock_gettime(clockid: i32, ts: *mut timespec) -> i32;
+}
@@ -0,0 +1,56 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+// Tracing of the ioctls through the `log` facade, like strace's decoding of the DRM ioctls: every
+// call of pxp_ioctl() is logged at the Debug level to the "pxp_ioctl" target, with the name of the
+// command, its decoded argument, the return code and the latency. The argument is printed as it is
+// after the call, i.e. with what the kernel reported back.
+
+pub(crate) const TARGET: &str = "pxp_ioctl";
+
+// The groups of commands which can be traced separately.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IoctlFamily {
+    // The core DRM commands: version, caps, auth, ... and the i915 GETPARAM.
+    Drm,
+    // The GEM objects: create, close, mmap, tiling, caching, prime, ...
+    Gem,
+    Context,
+    // The address spaces: VM create and destroy, VM_BIND, user fences, UUIDs.
+    Vm,
+    // EXECBUFFER2
+    Exec,
+    Query,
+    Pxp,
+    Perf,
+    Syncobj,
+}
+
+impl IoctlFamily {
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+// The families traced, one bit per IoctlFamily.
+static FILTER: AtomicU32 = AtomicU32::new(!0);
+
+// Only trace the commands of the given families, e.g. `set_trace_filter(&[IoctlFamily::Pxp])`.
+// The commands unknown to the crate are always traced.
+pub fn set_trace_filter(families: &[IoctlFamily]) {
+    let mask = families.iter().fold(0, |mask, family| mask | family.bit());
+    FILTER.store(mask, Ordering::Relaxed);
+}
+
+// Trace the commands of all families again, which is the default.
+pub fn trace_all() {
+    FILTER.store(!0, Ordering::Relaxed);
+}
+
+// Whether a command of family should be traced. None for an unknown command.
+pub(crate) fn traced(family: Option<IoctlFamily>) -> bool {
+    let selected = match family {
+        Some(family) => FILTER.load(Ordering::Relaxed) & family.bit() != 0,
+        None => true,
+    };
+    selected && log_enabled!(target: TARGET, log::Level::Debug)
+}
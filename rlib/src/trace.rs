@@ -0,0 +1,478 @@
+use crate::error::Error;
+use crate::memory::allocation_of;
+use crate::transport::{set_transport, transport, Transport};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{mem, ptr};
+use spin::{Mutex, RwLock};
+
+// Recording of the ioctls handed to the host, and their replay outside SGX.
+//
+// The trace is a header followed by one record per ioctl, all little-endian:
+//   header: "PXPT" version:u32
+//   record: fd:i32 cmd:u32 arg:u64 ret:i32 errno:i32 count:u32 block*count count:u32 field*count
+//   block:  addr:u64 size:u32 access:u32 input:[u8; size]? output:[u8; size]?
+//   field:  block:u32 offset:u32
+// The blocks are the marshalled argument tree: the untrusted copy of the argument and the buffers its
+// pointer fields point to, directly or through another block, as listed by DeepCopy::pointers(). The
+// input is the block as given to the host, only kept if the kernel reads it, and the output the block
+// as returned by the host, only kept if the kernel writes it. The fields are the pointers between the
+// blocks, which are moved when the tree is replayed at other addresses.
+
+const MAGIC: &[u8; 4] = b"PXPT";
+const VERSION: u32 = 2;
+// The access of a block: read and/or written by the kernel.
+const INPUT: u32 = 1 << 0;
+const OUTPUT: u32 = 1 << 1;
+// The trace is kept inside enclave, so it stops growing at this size. The ioctls past it aren't
+// recorded.
+const MAX_TRACE_SIZE: usize = 16 << 20;
+
+// A pointer field of a marshalled argument: its address, and whether the buffer it points to is read
+// and/or written by the kernel.
+pub(crate) struct Pointer {
+    pub field: u64,
+    pub input: bool,
+    pub output: bool,
+}
+
+struct Recorder {
+    // The transport the ioctls are forwarded to.
+    inner: RwLock<Option<&'static dyn Transport>>,
+    trace: Mutex<Vec<u8>>,
+    // The pointer fields of the arguments about to be handed to the host: [ arg -> pointers ]
+    pointers: Mutex<BTreeMap<u64, Vec<Pointer>>>,
+}
+
+static RECORDER: Recorder = Recorder {
+    inner: RwLock::new(None),
+    trace: Mutex::new(Vec::new()),
+    pointers: Mutex::new(BTreeMap::new()),
+};
+static RECORDING: AtomicBool = AtomicBool::new(false);
+// Whether the trace has reached MAX_TRACE_SIZE.
+static FULL: AtomicBool = AtomicBool::new(false);
+
+// Record the ioctls from now on, until stop_recording(). The recorder is put in front of the current
+// transport.
+pub fn start_recording() {
+    if RECORDING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    *RECORDER.inner.write() = Some(transport());
+    RECORDER.pointers.lock().clear();
+    FULL.store(false, Ordering::SeqCst);
+    let mut trace = RECORDER.trace.lock();
+    trace.clear();
+    trace.extend_from_slice(MAGIC);
+    trace.extend_from_slice(&VERSION.to_le_bytes());
+    set_transport(&RECORDER);
+}
+
+// Stop recording and return the trace. Empty if not recording.
+pub fn stop_recording() -> Vec<u8> {
+    if !RECORDING.swap(false, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    // The inner transport is kept, for the ioctls still in flight through the recorder.
+    if let Some(inner) = *RECORDER.inner.read() {
+        set_transport(inner);
+    }
+    RECORDER.pointers.lock().clear();
+    mem::take(&mut *RECORDER.trace.lock())
+}
+
+pub(crate) fn recording() -> bool {
+    RECORDING.load(Ordering::SeqCst)
+}
+
+// Describe the pointer fields of arg, for its coming ioctl. Without them, only the argument itself is
+// recorded.
+pub(crate) fn set_pointers(arg: u64, pointers: Vec<Pointer>) {
+    RECORDER.pointers.lock().insert(arg, pointers);
+}
+
+fn read_block(addr: usize, size: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; size];
+    unsafe { ptr::copy(addr as *const u8, bytes.as_mut_ptr(), size) };
+    bytes
+}
+
+// A block of the argument tree: an allocation of memory.rs, and its access.
+struct TreeBlock {
+    addr: usize,
+    size: usize,
+    access: u32,
+}
+
+// The blocks reachable from arg, the one containing arg first, and the pointer fields between them as
+// (block, offset).
+fn argument_tree(arg: u64, pointers: &[Pointer]) -> (Vec<TreeBlock>, Vec<(u32, u32)>) {
+    let mut blocks = Vec::new();
+    let mut fields = Vec::new();
+    match allocation_of(arg as usize) {
+        Some((addr, size)) => blocks.push(TreeBlock {
+            addr,
+            size,
+            access: INPUT | OUTPUT,
+        }),
+        None => return (blocks, fields),
+    }
+    // A buffer is listed after the field pointing to it, so the block of each field is known.
+    for pointer in pointers {
+        let field = pointer.field as usize;
+        let parent = match blocks.iter().position(|block| {
+            field >= block.addr && field + mem::size_of::<u64>() <= block.addr + block.size
+        }) {
+            Some(parent) => parent,
+            None => continue,
+        };
+        let value = unsafe { ptr::read_unaligned(field as *const u64) };
+        let (addr, size) = match allocation_of(value as usize) {
+            Some(block) => block,
+            // NULL, or a buffer of the application which isn't translated.
+            None => continue,
+        };
+        let mut access = 0;
+        if pointer.input {
+            access |= INPUT;
+        }
+        if pointer.output {
+            access |= OUTPUT;
+        }
+        match blocks.iter_mut().find(|block| block.addr == addr) {
+            Some(block) => block.access |= access,
+            None => blocks.push(TreeBlock { addr, size, access }),
+        }
+        fields.push((parent as u32, (field - blocks[parent].addr) as u32));
+    }
+    (blocks, fields)
+}
+
+fn result_of(ret: &Result<i32, Error>) -> Option<(i32, i32)> {
+    match ret {
+        Ok(ret) => Some((*ret, 0)),
+        Err(Error::HostIoctl(errno)) => Some((-1, *errno)),
+        // The host wasn't reached.
+        Err(_) => None,
+    }
+}
+
+impl Recorder {
+    fn inner(&self) -> Result<&'static dyn Transport, Error> {
+        (*self.inner.read()).ok_or(Error::InvalidArgument(String::from("trace: not recording")))
+    }
+}
+
+impl Transport for Recorder {
+    fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+        let pointers = self.pointers.lock().remove(&arg).unwrap_or_default();
+        if FULL.load(Ordering::SeqCst) {
+            return self.inner()?.ioctl(fd, request, arg);
+        }
+        let (blocks, fields) = argument_tree(arg, &pointers);
+        let input: Vec<Option<Vec<u8>>> = blocks
+            .iter()
+            .map(|block| (block.access & INPUT != 0).then(|| read_block(block.addr, block.size)))
+            .collect();
+        let ret = self.inner()?.ioctl(fd, request, arg);
+        let (result, errno) = match result_of(&ret) {
+            Some(result) => result,
+            None => return ret,
+        };
+        let mut record = Vec::new();
+        record.extend_from_slice(&fd.to_le_bytes());
+        record.extend_from_slice(&request.to_le_bytes());
+        record.extend_from_slice(&arg.to_le_bytes());
+        record.extend_from_slice(&result.to_le_bytes());
+        record.extend_from_slice(&errno.to_le_bytes());
+        record.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for (block, input) in blocks.iter().zip(input) {
+            record.extend_from_slice(&(block.addr as u64).to_le_bytes());
+            record.extend_from_slice(&(block.size as u32).to_le_bytes());
+            record.extend_from_slice(&block.access.to_le_bytes());
+            if let Some(input) = input {
+                record.extend_from_slice(&input);
+            }
+            if block.access & OUTPUT != 0 {
+                record.extend_from_slice(&read_block(block.addr, block.size));
+            }
+        }
+        record.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        for (block, offset) in fields {
+            record.extend_from_slice(&block.to_le_bytes());
+            record.extend_from_slice(&offset.to_le_bytes());
+        }
+        let mut trace = self.trace.lock();
+        // A trace with a hole couldn't be replayed, so the recording stops at the first ioctl which
+        // doesn't fit.
+        if trace.len() + record.len() > MAX_TRACE_SIZE {
+            if !FULL.swap(true, Ordering::SeqCst) {
+                warn!(
+                    "pxp-rs: trace: 0x{:x} bytes reached, the next ioctls aren't recorded",
+                    trace.len()
+                );
+            }
+        } else {
+            trace.extend_from_slice(&record);
+        }
+        ret
+    }
+    fn alloc(&self, size: usize, align: usize) -> Result<*mut u8, Error> {
+        self.inner()?.alloc(size, align)
+    }
+    fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+        self.inner()?.free(ptr)
+    }
+    fn mmap(
+        &self,
+        addr: *mut u8,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> Result<*mut u8, Error> {
+        self.inner()?.mmap(addr, len, prot, flags, fd, offset)
+    }
+    fn munmap(&self, addr: *mut u8, len: usize) -> Result<(), Error> {
+        self.inner()?.munmap(addr, len)
+    }
+    fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool {
+        match self.inner() {
+            Ok(inner) => inner.is_outside_enclave(ptr, size),
+            Err(_) => false,
+        }
+    }
+    fn clock(&self) -> Option<u64> {
+        self.inner().ok()?.clock()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(size)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(Error::InvalidArgument(format!(
+                "trace: truncated at 0x{:x}",
+                self.pos
+            )))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn done(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
+
+struct Block<'a> {
+    addr: usize,
+    size: usize,
+    input: Option<&'a [u8]>,
+    output: Option<&'a [u8]>,
+}
+
+struct Record<'a> {
+    fd: i32,
+    cmd: u32,
+    arg: u64,
+    // (ret, errno)
+    result: (i32, i32),
+    blocks: Vec<Block<'a>>,
+    // (block, offset)
+    fields: Vec<(usize, usize)>,
+}
+
+impl<'a> Reader<'a> {
+    fn record(&mut self) -> Result<Record<'a>, Error> {
+        let fd = self.i32()?;
+        let cmd = self.u32()?;
+        let arg = self.u64()?;
+        let result = (self.i32()?, self.i32()?);
+        let mut blocks = Vec::new();
+        for _ in 0..self.u32()? {
+            let addr = self.u64()? as usize;
+            let size = self.u32()? as usize;
+            let access = self.u32()?;
+            let input = if access & INPUT != 0 {
+                Some(self.take(size)?)
+            } else {
+                None
+            };
+            let output = if access & OUTPUT != 0 {
+                Some(self.take(size)?)
+            } else {
+                None
+            };
+            blocks.push(Block {
+                addr,
+                size,
+                input,
+                output,
+            });
+        }
+        let mut fields = Vec::new();
+        for _ in 0..self.u32()? {
+            let (block, offset) = (self.u32()? as usize, self.u32()? as usize);
+            match blocks.get(block) {
+                Some(b) if offset + mem::size_of::<u64>() <= b.size => fields.push((block, offset)),
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "trace: bad pointer field at 0x{:x}",
+                        self.pos
+                    )))
+                }
+            }
+        }
+        Ok(Record {
+            fd,
+            cmd,
+            arg,
+            result,
+            blocks,
+            fields,
+        })
+    }
+}
+
+// Block `index` of the record, with its pointer fields moved into the replayed blocks.
+fn relocate(bytes: &[u8], index: usize, record: &Record, bases: &[usize]) -> Vec<u8> {
+    let mut relocated = bytes.to_vec();
+    for (block, offset) in record.fields.iter() {
+        if *block != index {
+            continue;
+        }
+        let range = *offset..*offset + mem::size_of::<u64>();
+        let value = u64::from_le_bytes(bytes[range.clone()].try_into().unwrap());
+        if let Some(new) = relocate_value(value as usize, &record.blocks, bases) {
+            relocated[range].copy_from_slice(&(new as u64).to_le_bytes());
+        }
+    }
+    relocated
+}
+
+fn relocate_value(value: usize, blocks: &[Block], bases: &[usize]) -> Option<usize> {
+    blocks
+        .iter()
+        .zip(bases)
+        .find(|(block, _)| value >= block.addr && value < block.addr + block.size)
+        .map(|(block, base)| base + (value - block.addr))
+}
+
+// A difference between the trace and its replay.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    // The index of the record in the trace
+    pub index: usize,
+    pub cmd: u32,
+    pub reason: String,
+}
+
+// Replay a trace of start_recording() through transport, e.g. the mock backend or the host's ioctl()
+// outside SGX, and return the differences. The fds of the trace are translated by map_fd. Handles and
+// ids are replayed as recorded, so the trace should start on a fresh fd.
+pub fn replay(
+    trace: &[u8],
+    transport: &dyn Transport,
+    map_fd: &dyn Fn(i32) -> i32,
+) -> Result<Vec<Mismatch>, Error> {
+    let mut reader = Reader {
+        bytes: trace,
+        pos: 0,
+    };
+    if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+        return Err(Error::InvalidArgument(String::from("trace: bad header")));
+    }
+    let mut mismatches = Vec::new();
+    let mut index = 0;
+    while !reader.done() {
+        let record = reader.record()?;
+        // The blocks are given back whatever the outcome of the record.
+        let mut bases = Vec::new();
+        let reasons = replay_record(&record, transport, map_fd, &mut bases);
+        for base in bases {
+            transport.free(base as *mut u8)?;
+        }
+        for reason in reasons? {
+            mismatches.push(Mismatch {
+                index,
+                cmd: record.cmd,
+                reason,
+            });
+        }
+        index += 1;
+    }
+    Ok(mismatches)
+}
+
+// Replay a record in blocks allocated from transport, which are added to bases, and return how it
+// differs from the trace.
+fn replay_record(
+    record: &Record,
+    transport: &dyn Transport,
+    map_fd: &dyn Fn(i32) -> i32,
+    bases: &mut Vec<usize>,
+) -> Result<Vec<String>, Error> {
+    for block in record.blocks.iter() {
+        let base = transport.alloc(block.size, mem::size_of::<u64>())?;
+        if base.is_null() {
+            return Err(Error::UntrustedAlloc(block.size));
+        }
+        bases.push(base as usize);
+    }
+    for (i, (block, base)) in record.blocks.iter().zip(bases.iter()).enumerate() {
+        // A block only written by the kernel is handed over zeroed.
+        let input = match block.input {
+            Some(input) => relocate(input, i, record, bases),
+            None => vec![0u8; block.size],
+        };
+        unsafe { ptr::copy(input.as_ptr(), *base as *mut u8, input.len()) };
+    }
+    let arg =
+        relocate_value(record.arg as usize, &record.blocks, bases).unwrap_or(record.arg as usize);
+    let ret = transport.ioctl(map_fd(record.fd), record.cmd, arg as u64);
+    let mut reasons = Vec::new();
+    match result_of(&ret) {
+        Some(replayed) if replayed != record.result => reasons.push(format!(
+            "returned {} (errno {}), recorded {} (errno {})",
+            replayed.0, replayed.1, record.result.0, record.result.1
+        )),
+        Some(_) => {}
+        None => return ret.map(|_| Vec::new()),
+    }
+    for (i, (block, base)) in record.blocks.iter().zip(bases.iter()).enumerate() {
+        let expected = match block.output {
+            Some(output) => relocate(output, i, record, bases),
+            None => continue,
+        };
+        let output = read_block(*base, expected.len());
+        if let Some(offset) = (0..expected.len()).find(|i| expected[*i] != output[*i]) {
+            reasons.push(format!(
+                "block {} (0x{:x} bytes) differs at offset 0x{:x}",
+                i,
+                expected.len(),
+                offset
+            ));
+        }
+    }
+    Ok(reasons)
+}
@@ -0,0 +1,299 @@
+use crate::error::Error;
+use spin::RwLock;
+
+// The way to reach the host: the OCALLs of the LibOS or SDK the enclave is built with. Each
+// operation returns once the host call is done, the host's errno is kept in the enclave's errno.
+pub trait Transport: Sync {
+    // ioctl(2) on the host, the argument is in untrusted memory.
+    fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error>;
+    // Allocate untrusted memory. The result is checked by the caller.
+    fn alloc(&self, size: usize, align: usize) -> Result<*mut u8, Error>;
+    fn free(&self, ptr: *mut u8) -> Result<(), Error>;
+    // mmap(2) on the host, returns MAP_FAILED on failure like it.
+    fn mmap(
+        &self,
+        addr: *mut u8,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> Result<*mut u8, Error>;
+    fn munmap(&self, addr: *mut u8, len: usize) -> Result<(), Error>;
+    fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool;
+    // The host's monotonic clock in nanoseconds, for the latency of the traced ioctls. None if the
+    // transport has no clock.
+    fn clock(&self) -> Option<u64> {
+        None
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "mock")] {
+        static DEFAULT: crate::mock::MockTransport = crate::mock::MockTransport;
+    } else if #[cfg(feature = "occlum")] {
+        static DEFAULT: OcclumTransport = OcclumTransport;
+    } else {
+        static DEFAULT: SgxSdkTransport = SgxSdkTransport;
+    }
+}
+static TRANSPORT: RwLock<&'static dyn Transport> = RwLock::new(&DEFAULT);
+
+// Select the transport, before the first pxp_ioctl(). The one of the build (Occlum or SGX SDK) is
+// used by default.
+pub fn set_transport(transport: &'static dyn Transport) {
+    *TRANSPORT.write() = transport;
+}
+
+pub fn transport() -> &'static dyn Transport {
+    *TRANSPORT.read()
+}
+
+// The OCALL backends aren't built for the mock, which has no enclave to call out of.
+cfg_if::cfg_if! {
+    if #[cfg(feature = "mock")] {
+    } else if #[cfg(feature = "occlum")] {
+        use crate::error::{errno, set_errno};
+        use core::ffi::c_void;
+        use sgx_types::sgx_status_t;
+
+        pub struct OcclumTransport;
+
+        impl Transport for OcclumTransport {
+            fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+                let mut ret: i32 = 0;
+                // The OCALL copies the host's errno back. Clear it first to avoid reporting a
+                // stale value.
+                set_errno(0);
+                check_status(unsafe {
+                    occlum_ocall_device_ioctl(&mut ret as *mut i32, fd, request as i32, arg)
+                })?;
+                if ret < 0 {
+                    return Err(Error::HostIoctl(errno()));
+                }
+                Ok(ret)
+            }
+            fn alloc(&self, size: usize, align: usize) -> Result<*mut u8, Error> {
+                let mut ptr: *mut c_void = core::ptr::null_mut();
+                check_status(unsafe {
+                    occlum_ocall_posix_memalign(&mut ptr as *mut _, align, size)
+                })?;
+                Ok(ptr as *mut u8)
+            }
+            fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+                check_status(unsafe { occlum_ocall_free(ptr as *mut c_void) })
+            }
+            fn mmap(
+                &self,
+                addr: *mut u8,
+                len: usize,
+                prot: i32,
+                flags: i32,
+                fd: i32,
+                offset: i64,
+            ) -> Result<*mut u8, Error> {
+                let mut ret: *mut c_void = core::ptr::null_mut();
+                set_errno(0);
+                check_status(unsafe {
+                    occlum_ocall_device_mmap(
+                        &mut ret as *mut _,
+                        addr as *mut c_void,
+                        len,
+                        prot,
+                        flags,
+                        fd,
+                        offset,
+                    )
+                })?;
+                Ok(ret as *mut u8)
+            }
+            fn munmap(&self, addr: *mut u8, len: usize) -> Result<(), Error> {
+                let mut ret: i32 = 0;
+                set_errno(0);
+                check_status(unsafe {
+                    occlum_ocall_device_munmap(&mut ret as *mut i32, addr as *mut c_void, len)
+                })?;
+                if ret < 0 {
+                    return Err(Error::HostMmap(errno()));
+                }
+                Ok(())
+            }
+            fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool {
+                sgx_trts::trts::rsgx_raw_is_outside_enclave(ptr, size)
+            }
+            fn clock(&self) -> Option<u64> {
+                let mut ts = timespec { tv_sec: 0, tv_nsec: 0 };
+                let status = unsafe { occlum_ocall_clock_gettime(CLOCK_MONOTONIC, &mut ts) };
+                if status != sgx_status_t::SGX_SUCCESS {
+                    return None;
+                }
+                ts.nanoseconds()
+            }
+        }
+
+        extern "C" {
+            fn occlum_ocall_device_ioctl(
+                ret: *mut i32,
+                fd: i32,  //c_int
+                request: i32,  //c_int
+                arg: u64,
+            ) -> sgx_status_t;
+            fn occlum_ocall_posix_memalign(
+                ptr: *mut *mut c_void,
+                align: usize, // must be power of two and a multiple of sizeof(void*)
+                size: usize,
+            ) -> sgx_status_t;
+            fn occlum_ocall_free(ptr: *mut c_void) -> sgx_status_t;
+            fn occlum_ocall_device_mmap(
+                ret: *mut *mut c_void,
+                addr: *mut c_void,
+                len: usize,
+                prot: i32,
+                flags: i32,
+                fd: i32,
+                offset: i64,
+            ) -> sgx_status_t;
+            fn occlum_ocall_device_munmap(
+                ret: *mut i32,
+                addr: *mut c_void,
+                len: usize,
+            ) -> sgx_status_t;
+            fn occlum_ocall_clock_gettime(clockid: i32, ts: *mut timespec) -> sgx_status_t;
+        }
+    } else {
+        use crate::error::{errno, set_errno};
+        use core::ffi::c_void;
+        use sgx_types::sgx_status_t;
+
+        pub struct SgxSdkTransport;
+
+        impl Transport for SgxSdkTransport {
+            fn ioctl(&self, fd: i32, request: u32, arg: u64) -> Result<i32, Error> {
+                let mut ret: i32 = 0;
+                // The OCALL is declared with `propagate_errno`, so the host's errno is copied back.
+                // Clear it first to avoid reporting a stale value.
+                set_errno(0);
+                check_status(unsafe {
+                    ocall_pxp_ioctl(&mut ret as *mut i32, fd, request as i32, arg)
+                })?;
+                if ret < 0 {
+                    return Err(Error::HostIoctl(errno()));
+                }
+                Ok(ret)
+            }
+            fn alloc(&self, size: usize, _align: usize) -> Result<*mut u8, Error> {
+                // malloc() is aligned enough for any type.
+                let mut ptr: *mut c_void = core::ptr::null_mut();
+                check_status(unsafe { u_malloc(&mut ptr as *mut _, size) })?;
+                Ok(ptr as *mut u8)
+            }
+            fn free(&self, ptr: *mut u8) -> Result<(), Error> {
+                check_status(unsafe { u_free(ptr as *mut c_void) })
+            }
+            fn mmap(
+                &self,
+                addr: *mut u8,
+                len: usize,
+                prot: i32,
+                flags: i32,
+                fd: i32,
+                offset: i64,
+            ) -> Result<*mut u8, Error> {
+                let mut ret: *mut c_void = core::ptr::null_mut();
+                set_errno(0);
+                check_status(unsafe {
+                    ocall_pxp_mmap(
+                        &mut ret as *mut _,
+                        addr as *mut c_void,
+                        len,
+                        prot,
+                        flags,
+                        fd,
+                        offset,
+                    )
+                })?;
+                Ok(ret as *mut u8)
+            }
+            fn munmap(&self, addr: *mut u8, len: usize) -> Result<(), Error> {
+                let mut ret: i32 = 0;
+                set_errno(0);
+                check_status(unsafe {
+                    ocall_pxp_munmap(&mut ret as *mut i32, addr as *mut c_void, len)
+                })?;
+                if ret < 0 {
+                    return Err(Error::HostMmap(errno()));
+                }
+                Ok(())
+            }
+            fn is_outside_enclave(&self, ptr: *const u8, size: usize) -> bool {
+                unsafe { sgx_is_outside_enclave(ptr as *const c_void, size) != 0 }
+            }
+            // ocall_pxp_clock is only declared with the `pxp_clock` feature, the ioctls are traced
+            // without their latency otherwise.
+            #[cfg(feature = "pxp_clock")]
+            fn clock(&self) -> Option<u64> {
+                let mut ns: u64 = 0;
+                let status = unsafe { ocall_pxp_clock(&mut ns as *mut u64) };
+                if status != sgx_status_t::SGX_SUCCESS {
+                    return None;
+                }
+                Some(ns)
+            }
+        }
+
+        extern "C" {
+            fn ocall_pxp_ioctl(
+                ret: *mut i32,
+                fd: i32,  //c_int
+                request: i32,  //c_int
+                arg: u64,
+            ) -> sgx_status_t;
+            fn u_malloc(ptr: *mut *mut c_void, size: usize) -> sgx_status_t;
+            fn u_free(ptr: *mut c_void) -> sgx_status_t;
+            fn ocall_pxp_mmap(
+                ret: *mut *mut c_void,
+                addr: *mut c_void,
+                len: usize,
+                prot: i32,
+                flags: i32,
+                fd: i32,
+                offset: i64,
+            ) -> sgx_status_t;
+            fn ocall_pxp_munmap(
+                ret: *mut i32,
+                addr: *mut c_void,
+                len: usize,
+            ) -> sgx_status_t;
+            fn sgx_is_outside_enclave(ptr: *const c_void, size: usize) -> i32;
+            #[cfg(feature = "pxp_clock")]
+            fn ocall_pxp_clock(ret: *mut u64) -> sgx_status_t;
+        }
+    }
+}
+
+#[cfg(not(feature = "mock"))]
+fn check_status(status: sgx_types::sgx_status_t) -> Result<(), Error> {
+    if status != sgx_types::sgx_status_t::SGX_SUCCESS {
+        return Err(Error::Ocall(status));
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "mock", feature = "occlum"))]
+pub(crate) const CLOCK_MONOTONIC: i32 = 1;
+
+#[cfg(any(feature = "mock", feature = "occlum"))]
+#[repr(C)]
+pub(crate) struct timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+#[cfg(any(feature = "mock", feature = "occlum"))]
+impl timespec {
+    pub fn nanoseconds(&self) -> Option<u64> {
+        (self.tv_sec as u64)
+            .checked_mul(1_000_000_000)?
+            .checked_add(self.tv_nsec as u64)
+    }
+}